@@ -0,0 +1,66 @@
+use std::io;
+use std::string::FromUtf8Error;
+use std::sync::mpsc::RecvError;
+
+use rodio::cpal::SupportedStreamConfigsError;
+use rodio::decoder::DecoderError;
+use rodio::{PlayError, StreamError};
+use thiserror::Error;
+
+/// Everything that can go wrong while playing along.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("{0} is not a server address, expected host:port or [ipv6]:port")]
+    InvalidAddress(String),
+
+    #[error("host not found: {0}")]
+    HostNotFound(String),
+
+    #[error("connection refused by {0}")]
+    ConnectionRefused(String),
+
+    #[error("could not connect to {0}: {1}")]
+    Connect(String, io::Error),
+
+    #[error("invalid JSON from the server: {0}")]
+    Decode(#[from] serde_json::Error),
+
+    #[error("invalid text from the server: {0}")]
+    Text(#[from] FromUtf8Error),
+
+    #[error("unknown command {0} from the server")]
+    UnknownCommand(u8),
+
+    #[error("the song ended after {received} of {expected} bytes")]
+    Truncated { received: u64, expected: u64 },
+
+    #[error("a payload of {0} bytes is too large to hold in memory, try --song-dir")]
+    TooLarge(u64),
+
+    #[error("could not decode the song: {0}")]
+    Song(#[from] DecoderError),
+
+    #[error("could not open the audio output: {0}")]
+    Stream(#[from] StreamError),
+
+    #[error("could not play on the audio output: {0}")]
+    Play(#[from] PlayError),
+
+    #[error("could not query the audio output: {0}")]
+    DeviceConfig(#[from] SupportedStreamConfigsError),
+
+    #[error("no audio output device found")]
+    NoDevice,
+
+    #[error("the audio output device does not support {0} Hz")]
+    UnsupportedSampleRate(u32),
+
+    #[error("must be run in an interactive terminal: {0}")]
+    Terminal(#[source] io::Error),
+
+    #[error("the event channel was closed")]
+    ChannelClosed(#[from] RecvError),
+}
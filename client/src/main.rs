@@ -1,14 +1,28 @@
+mod error;
+
 use std::{
-    error::Error,
-    io::{self, Cursor, ErrorKind, Read, Write},
-    net::TcpStream,
+    env,
+    fs::{self, File},
+    io::{self, BufReader, Cursor, IsTerminal, Read, Seek, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    process,
 };
 use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
 use ratatui::{DefaultTerminal, Frame};
-use rodio::{Decoder, OutputStream, Sink};
-use std::sync::mpsc;
+use clap::{Parser, ValueEnum};
+use log::{error, info, warn, LevelFilter};
+use rodio::cpal::traits::HostTrait;
+use rodio::cpal::SampleRate;
+use rodio::decoder::DecoderError;
+use rodio::source::SeekError;
+use rodio::{Decoder, DeviceTrait, OutputStream, OutputStreamHandle, Sink, Source};
+use std::f32::consts::TAU;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
 use crossterm::event;
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
@@ -16,36 +30,405 @@ use ratatui::buffer::Buffer;
 use ratatui::layout::{Constraint, Flex, Layout, Rect};
 use ratatui::text::Line;
 use ratatui::widgets::{Block, Clear, Paragraph, Widget, Gauge};
-use ratatui::style::Stylize;
+use ratatui::style::{Color, Stylize};
+use ratatui::text::Span;
+use serde::{Deserialize, Serialize};
+
+use error::ClientError;
+
+#[derive(Parser, Debug)]
+#[command(about = "Join a music quiz")]
+struct Args {
+    /// Force the audio output to this sample rate in Hz, songs are resampled to match
+    #[arg(long)]
+    sample_rate: Option<u32>,
+
+    /// Store received songs as files in this directory instead of keeping them in memory
+    #[arg(long)]
+    song_dir: Option<PathBuf>,
+
+    /// How often to try reconnecting after losing the server before giving up
+    #[arg(long, default_value_t = 5)]
+    reconnect_attempts: u32,
+
+    /// Seconds a started message may stall before the server counts as lost, 0 waits forever
+    #[arg(long, default_value_t = 30)]
+    read_timeout: u64,
+
+    /// How gradings are shown when the host reveals an answer
+    #[arg(long, value_enum, default_value_t = GradeStyle::Words)]
+    grade_style: GradeStyle,
+
+    /// List the correct answer and each grade on separate lines when revealing
+    #[arg(long)]
+    detailed_reveal: bool,
+
+    /// Color the host uses for your nickname, a name like "cyan" or "#ff8800"
+    #[arg(long)]
+    color: Option<String>,
+
+    /// Show the host's scoreboard instead of the player view, for a shared screen
+    #[arg(long)]
+    mirror: bool,
+
+    /// Server to connect to, together with --nickname this joins right away
+    #[arg(long)]
+    server: Option<String>,
+
+    /// Nickname to prefill, for regulars who always play under the same name
+    #[arg(long)]
+    nickname: Option<String>,
+
+    /// Accept the given nickname right away and go straight to the server address
+    #[arg(long, requires = "nickname")]
+    skip_nickname: bool,
+
+    /// Lowest volume in percent the - key can turn down to
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u8).range(0..=100))]
+    min_volume: u8,
+
+    /// Let the main panel pulse with the loudness of the playing song
+    #[arg(long)]
+    ambiance: bool,
+
+    /// Milliseconds to fade out a playing song and fade in the next one when songs change
+    #[arg(long, default_value_t = 0)]
+    fade: u64,
+
+    /// Seconds after which a shown reveal goes back to the paused screen, 0 keeps it until the next song
+    #[arg(long, default_value_t = 0)]
+    reveal_timeout: u64,
+
+    /// Play the songs in this directory without a server, to check audio and decoding
+    #[arg(long, conflicts_with = "server")]
+    solo: Option<PathBuf>,
+
+    /// Log connection attempts, disconnects and playback problems to this file
+    #[arg(long)]
+    log: Option<PathBuf>
+}
+
+/// Sent once after connecting to introduce this client.
+#[derive(Serialize)]
+struct Handshake<'a> {
+    nickname: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<&'a str>,
+    mirror: bool,
+    /// Lets the server skip sending the song again after a reconnect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    song_hash: Option<u64>
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum GradeStyle {
+    /// "correct" / "incorrect"
+    Words,
+    /// A filled or empty star
+    Stars,
+    /// The points the answer earned
+    Points
+}
+
+/// Pause before the first reconnect attempt, doubled after every failed one.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Longest pause between two reconnect attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Largest payload held in memory, bigger songs need --song-dir.
+const MAX_IN_MEMORY_PAYLOAD: u64 = 1 << 30;
+
+/// Interval of the tick event that drives time based updates.
+const TICK_RATE: Duration = Duration::from_millis(250);
+
+/// Starts every message from the server, followed by the length of the rest.
+const FRAME_MAGIC: [u8; 4] = *b"MUSQ";
+
+/// Share of the distance to the current level the ambiance panel covers per tick.
+const AMBIANCE_SMOOTHING: f32 = 0.4;
+
+/// Volume used when no earlier one was saved.
+const DEFAULT_VOLUME: f32 = 0.5;
+
+/// How far + and - move the volume.
+const VOLUME_STEP: f32 = 0.05;
+
+/// How far ] and [ move the volume, for fine adjustments.
+const FINE_VOLUME_STEP: f32 = 0.01;
+
+/// Longest wait for a scheduled start, more means the clocks disagree.
+const MAX_START_DELAY: Duration = Duration::from_secs(2);
+
+/// How often the server is told this client is still around.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Consecutive terminal read failures after which the input thread gives up.
+const MAX_INPUT_FAILURES: u32 = 10;
 
 #[derive(Clone)]
 enum Command {
     Play,
     Transfer,
     Pause,
-    Repeat
+    Repeat,
+    Motd,
+    Reveal,
+    Chime,
+    PlayState,
+    Hint,
+    HostState,
+    Resync,
+    QuizName,
+    ShowAnswer,
+    Queued,
+    SeekForward,
+    SeekBack,
+    Summary,
+    Shutdown,
+    Countdown,
+    PreloadStart,
+    PreloadChunk,
+    SwapPreloaded,
+    KeepSong
+}
+
+/// Messages sent to the server after the handshake.
+enum Request {
+    Resend,
+    Leave,
+    Heartbeat,
+    Buzz,
+    /// Followed by the guess as a length prefixed string
+    Guess
+}
+
+impl Request {
+    fn code(&self) -> u8 {
+        match self {
+            Request::Resend => 5,
+            Request::Leave => 6,
+            Request::Heartbeat => 7,
+            Request::Buzz => 8,
+            Request::Guess => 9
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct TitleGrading {
+    title: String,
+    interpret: String,
+    title_correct: Option<bool>,
+    interpret_correct: Option<bool>,
+    #[serde(default)]
+    title_points: u32,
+    #[serde(default)]
+    interpret_points: u32,
+    /// Seconds to keep the answer hidden after the reveal
+    #[serde(default)]
+    reveal_delay: u64
+}
+
+/// The host screen as sent to mirror clients.
+#[derive(Deserialize, Debug, Clone)]
+struct HostState {
+    song: usize,
+    total: usize,
+    titles_correct: usize,
+    interprets_correct: usize,
+    disputed: usize,
+    points: u32,
+    elapsed_secs: u64,
+    time_left_secs: Option<u64>,
+    answer: Option<(String, String)>,
+    players: Vec<String>,
+    game_over: Option<String>
+}
+
+/// A player's final result, sent when the game ends.
+#[derive(Deserialize, Debug, Clone)]
+struct Summary {
+    points: u32,
+    possible: u32,
+    songs: usize
+}
+
+/// The host's authoritative state, sent when it forces clients back in line.
+#[derive(Deserialize, Debug)]
+struct Resync {
+    song: usize,
+    total: usize,
+    playing: bool,
+    transfered: bool
+}
+
+/// The plain answer to the current song, shown without any grading.
+#[derive(Deserialize, Debug, Clone)]
+struct Answer {
+    title: String,
+    interpret: String
+}
+
+/// The song after the current one, filled while the current one plays.
+struct Preload {
+    data: Vec<u8>,
+    format: Option<String>,
+    size: u64
+}
+
+impl Preload {
+    fn is_complete(&self) -> bool {
+        self.data.len() as u64 == self.size
+    }
+}
+
+/// A received song, either held in memory or spilled to a file.
+#[derive(Clone)]
+enum Song {
+    /// The data and the file extension the server sent, if any
+    Memory(Vec<u8>, Option<String>),
+    /// Stored under the server's file extension
+    File(PathBuf)
+}
+
+impl Song {
+    fn hash(&self) -> io::Result<u64> {
+        match self {
+            Song::Memory(data, _) => song_hash(data.as_slice()),
+            Song::File(path) => song_hash(BufReader::new(File::open(path)?))
+        }
+    }
 }
 
 enum AppEvent {
     Command(Command),
-    SongData(Vec<u8>),
+    SongData(Song),
+    Motd(String),
+    QuizName(String),
+    Answer(Answer),
+    /// Place in the server's queue, 0 once a slot is free
+    Queued(u64),
+    /// Move playback forward (true) or back by the step
+    Seek(bool, Duration),
+    /// Seconds left of the host's countdown and its full length
+    Countdown(u64, u64),
+    /// Play, started by the stream handler at this wall clock time in milliseconds
+    PlayAt(u64),
+    /// Number of the current song and how many songs the quiz has
+    SongPosition(usize, usize),
+    /// The next song is coming ahead of time, in its format and with this many bytes
+    PreloadStart(Option<String>, u64),
+    PreloadChunk(Vec<u8>),
+    Summary(Summary),
+    TitleGrading(TitleGrading),
+    Hint(String),
+    HostState(HostState),
+    Resync(Resync),
+    PlayState(bool),
+    Disconnected,
+    Reconnect,
+    DismissReveal(u64),
+    InputError(String),
+    InputRecovered,
+    InputLost,
+    Tick,
     CrossTerm(crossterm::event::Event)
 }
 
 enum AppState{
     EnterNickname,
     Disconnected,
+    Ready,
     Paused,
-    Playing
+    Playing,
+    Revealing,
+    ShowingAnswer,
+    PlaybackFinished,
+    Reconnecting(u32),
+    Queued(u64),
+    /// The host ended the quiz and closed the server
+    Finished
+}
+
+/// Local songs played without a server, in name order.
+struct Solo {
+    songs: Vec<PathBuf>,
+    index: usize
+}
+
+/// File extensions solo mode picks up, the formats `decode` knows.
+const SOLO_FORMATS: [&str; 4] = ["mp3", "flac", "ogg", "wav"];
+
+fn solo_songs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut songs: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| SOLO_FORMATS.contains(&ext.to_lowercase().as_str())))
+        .collect();
+    songs.sort();
+    Ok(songs)
+}
+
+/// Remembered between sessions in the client's config file.
+#[derive(Serialize, Deserialize)]
+struct Settings {
+    volume: f32
+}
+
+/// The settings file inside the platform's config directory, if one can be found.
+fn settings_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("musicquiz").join("client.json"))
+}
+
+/// The last saved volume, a missing or unreadable file falls back to the default.
+fn load_volume(path: &Path) -> f32 {
+    fs::read_to_string(path).ok()
+        .and_then(|json| serde_json::from_str::<Settings>(&json).ok())
+        .map(|settings| settings.volume)
+        .filter(|volume| (0.0..=1.0).contains(volume))
+        .unwrap_or(DEFAULT_VOLUME)
+}
+
+fn save_volume(path: &Path, volume: f32) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string(&Settings { volume })?)
+}
+
+/// Where the client starts, an accepted nickname skips its prompt.
+fn initial_state(nickname: &str, skip_nickname: bool) -> AppState {
+    if skip_nickname && !nickname.is_empty() {
+        AppState::Disconnected
+    } else {
+        AppState::EnterNickname
+    }
 }
 
 impl Display for AppState {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let AppState::Reconnecting(attempt) = self {
+            return write!(f, "RECONNECTING ({})", attempt);
+        }
+        if let AppState::Queued(position) = self {
+            return write!(f, "QUIZ FULL - WAITING IN QUEUE (#{})", position);
+        }
+
         let display = match self {
             AppState::EnterNickname => { "NICKNAME CONFIG" }
             AppState::Disconnected => { "DISCONNECTED" }
+            AppState::Ready => { "READY - NEW SONG LOADED" }
             AppState::Paused => { "PAUSED" }
             AppState::Playing => { "PLAYING" }
+            AppState::Revealing => { "REVEALING" }
+            AppState::ShowingAnswer => { "ANSWER" }
+            AppState::PlaybackFinished => { "PLAYBACK FINISHED" }
+            AppState::Reconnecting(_) => { "RECONNECTING" }
+            AppState::Queued(_) => { "QUEUED" }
+            AppState::Finished => { "QUIZ FINISHED" }
         };
         f.write_str(display)?;
         Ok(())
@@ -54,323 +437,2734 @@ impl Display for AppState {
 struct App{
     connection_string: String,
     nickname: String,
+    motd: Option<String>,
+    quiz_name: Option<String>,
     state: AppState,
     event_loop: Receiver<AppEvent>,
     stream: Option<thread::JoinHandle<()>>,
+    connection: Option<TcpStream>,
     event_sender: Sender<AppEvent>,
-    current_song: Option<Vec<u8>>,
-    sink: Sink,
+    current_song: Option<Song>,
+    song_dir: Option<PathBuf>,
+    reconnect_attempts: u32,
+    read_timeout: Option<Duration>,
+    status_message: Option<String>,
+    input_warning: Option<String>,
+    current_title_grading: Option<TitleGrading>,
+    current_answer: Option<Answer>,
+    reveal_at: Option<Instant>,
+    reveal_timeout: Option<Duration>,
+    /// Bumped on every reveal and grading so a pending dismiss only hides the reveal it was set for.
+    reveal_generation: u64,
+    hints: Vec<String>,
+    grade_style: GradeStyle,
+    detailed_reveal: bool,
+    color: Option<String>,
+    mirror: bool,
+    host_state: Option<HostState>,
+    song_position: Option<(usize, usize)>,
+    /// Length of the loaded song, unknown for some formats
+    song_duration: Option<Duration>,
+    song_elapsed: Duration,
+    last_heartbeat: Instant,
+    audio: Box<dyn AudioOutput>,
     volume: f32,
+    min_volume: f32,
+    /// The volume to go back to while muted
+    muted: Option<f32>,
+    /// Set when playing local songs, no server is involved then
+    solo: Option<Solo>,
+    ambiance: bool,
+    buzzed: bool,
+    /// The guess being typed, keys go to it while open
+    guess: Option<String>,
+    summary: Option<Summary>,
+    /// Seconds left of the running countdown and its full length
+    countdown: Option<(u64, u64)>,
+    next_song: Option<Preload>,
+    /// Smoothed song level driving the ambiance panel
+    energy: f32,
+    loading: bool,
     exit: bool
 }
 
-struct NickNamePopup{
-    nickname: String
+/// Everything the client needs from the audio backend, kept behind a trait so
+/// the state machine can be driven without a sound device.
+trait AudioOutput {
+    /// Replaces the current song, returns its length if the decoder knows it
+    fn load(&mut self, song: Song) -> Result<Option<Duration>, ClientError>;
+    /// How far into the current song playback is
+    fn position(&self) -> Duration;
+    /// Recent peak level of the playing song, from 0 to 1
+    fn level(&self) -> f32;
+    fn seek(&mut self, position: Duration) -> Result<(), SeekError>;
+    fn play(&mut self);
+    fn pause(&mut self);
+    fn set_volume(&mut self, volume: f32);
+    fn is_finished(&self) -> bool;
+    fn play_tone(&mut self, tone: Tone, volume: f32);
 }
 
-impl Widget for NickNamePopup {
-    fn render(self, area: Rect, buf: &mut Buffer) {
-        let block = Block::bordered().title(" Enter Nickname ");
-        Paragraph::new(vec![
-            Line::from(vec![
-                self.nickname.as_str().gray().bold()
-            ])
-        ]).block(block).gray().render(area, buf);
+struct RodioOutput {
+    sink: Sink,
+    handle: OutputStreamHandle,
+    level: Arc<AtomicU32>,
+    fade: Duration,
+    /// Tells the current song to fade out
+    fade_out: Arc<AtomicBool>,
+    /// The previous song while it fades out on its own sink
+    fading: Option<Sink>
+}
+
+impl RodioOutput {
+    /// Queues a decoded song with its fades, returns its length if known.
+    fn append<S: Source<Item = i16> + Send + 'static>(&mut self, source: S) -> Option<Duration> {
+        let duration = source.total_duration();
+        let source = FadeOut::new(source.fade_in(self.fade), self.fade_out.clone(), self.fade);
+        self.sink.append(LevelTap::new(source, self.level.clone()));
+        duration
     }
 }
 
-struct ServerPopup{
-    url: String
+/// Lowers a song to silence over the fade once told to, then ends it.
+struct FadeOut<S> {
+    inner: S,
+    trigger: Arc<AtomicBool>,
+    samples: u32,
+    left: Option<u32>
 }
 
-impl Widget for ServerPopup {
-    fn render(self, area: Rect, buf: &mut Buffer) {
-        let block = Block::bordered().title(" Enter Server Url ");
-        Paragraph::new(vec![
-            Line::from(vec![
-                self.url.as_str().gray().bold()
-            ])
-        ]).block(block).gray().render(area, buf);
+impl<S: Source<Item = i16>> FadeOut<S> {
+    fn new(inner: S, trigger: Arc<AtomicBool>, fade: Duration) -> FadeOut<S> {
+        let samples = (fade.as_secs_f32() * inner.sample_rate() as f32 * inner.channels() as f32) as u32;
+        FadeOut { inner, trigger, samples, left: None }
     }
 }
 
-impl App {
-    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn Error>> {
-        while !self.exit {
-            terminal.draw(|frame| self.draw(frame))?;
-            self.handle_events()?
+impl<S: Source<Item = i16>> Iterator for FadeOut<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.left.is_none() && self.trigger.load(Ordering::Relaxed) {
+            self.left = Some(self.samples);
+        }
+        let sample = self.inner.next()?;
+        match self.left.as_mut() {
+            None => Some(sample),
+            Some(0) => None,
+            Some(left) => {
+                *left -= 1;
+                Some((sample as f32 * *left as f32 / self.samples as f32) as i16)
+            }
         }
-        Ok(())
     }
-    fn draw(&self, frame: &mut Frame) {
-        let area = frame.area();
+}
 
-        let layout = Layout::vertical(vec![
-            Constraint::Percentage(80),
-            Constraint::Fill(1)
-        ]).split(area);
+impl<S: Source<Item = i16>> Source for FadeOut<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
 
-        let show_popup = matches!(self.state, AppState::EnterNickname | AppState::Disconnected);
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
 
-        let block = Block::bordered().title(" Music Quiz Client ");
-        Paragraph::new(vec![
-            Line::from(vec![
-                "Nickname: ".into(),
-                self.nickname.clone().yellow().bold()
-            ]),
-            Line::from(vec![
-                "Server url: ".into(),
-                self.connection_string.clone().blue().bold()
-            ]),
-            Line::from(vec![
-                "Status: ".into(),
-                format!("{}", &self.state).green().bold()
-            ])
-        ]).block(block).render(layout[0], frame.buffer_mut());
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
 
-        let audio_block = Block::bordered().title(" Audio Level ");
-        Gauge::default().block(audio_block).percent((self.volume * 100.0 )as u16).render(layout[1], frame.buffer_mut());
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
 
-        if show_popup {
-            let area = popup_area(area, 60, 20);
-            frame.render_widget(Clear, area); //this clears out the background
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
 
-            match self.state {
-                AppState::EnterNickname => {
-                    frame.render_widget(NickNamePopup{nickname: self.nickname.clone()}, area);
-                }
-                AppState::Disconnected => {
-                    frame.render_widget(ServerPopup{url: self.connection_string.clone()}, area);
-                }
-                _ => {}
-            }
-        }
+/// Samples per level reading, about 10 per second for stereo at 44.1 kHz.
+const LEVEL_WINDOW: u32 = 8192;
+
+/// Passes a song through unchanged while publishing its peak level per window.
+struct LevelTap<S> {
+    inner: S,
+    level: Arc<AtomicU32>,
+    peak: f32,
+    count: u32
+}
+
+impl<S> LevelTap<S> {
+    fn new(inner: S, level: Arc<AtomicU32>) -> LevelTap<S> {
+        LevelTap { inner, level, peak: 0.0, count: 0 }
     }
-    fn handle_events(&mut self) -> Result<(), Box<dyn Error>> {
+}
 
-        match self.event_loop.recv()? {
-            AppEvent::Command(cmd) => {
-                match cmd {
-                    Command::Play => { self.play() }
-                    Command::Transfer => { /*Should not happen TM*/ }
-                    Command::Pause => { self.pause() }
-                    Command::Repeat => {
-                        if let Some(song) = self.current_song.clone() {
-                            self.append_song(song).unwrap();
-                        }
-                    }
-                }
-            }
-            AppEvent::SongData(song) => {
-                self.current_song = Some(song.clone());
-                self.append_song(song).unwrap();
-            }
-            AppEvent::CrossTerm(event) => match event {
-                Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
-                    match self.state {
-                        AppState::EnterNickname => { self.handle_nickname_input(key_event); }
-                        AppState::Disconnected => { self.handle_url_input(key_event); }
-                        _ => { self.handle_input(key_event); }
-                    }
-                }
-                _ => {}
-            }
+impl<S: Source<Item = i16>> Iterator for LevelTap<S> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.inner.next()?;
+        self.peak = self.peak.max((sample as f32 / i16::MAX as f32).abs());
+        self.count += 1;
+        if self.count >= LEVEL_WINDOW {
+            self.level.store(self.peak.to_bits(), Ordering::Relaxed);
+            self.peak = 0.0;
+            self.count = 0;
         }
+        Some(sample)
+    }
+}
 
-        Ok(())
+impl<S: Source<Item = i16>> Source for LevelTap<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
     }
 
-    fn handle_nickname_input(&mut self, event: KeyEvent) {
-        match event.code {
-            KeyCode::Char(new) => {
-                self.nickname.push(new);
-            }
-            KeyCode::Backspace => {
-                self.nickname.pop();
-            }
-            KeyCode::Enter => {
-                self.state = AppState::Disconnected;
-            }
-            KeyCode::Esc => {
-                self.exit = true;
-            }
-            _ => {}
-        }
+    fn channels(&self) -> u16 {
+        self.inner.channels()
     }
 
-    fn handle_url_input(&mut self, event: KeyEvent) {
-        match event.code {
-            KeyCode::Char(new) => {
-                self.connection_string.push(new);
-            }
-            KeyCode::Backspace => {
-                self.connection_string.pop();
-            }
-            KeyCode::Enter => {
-                self.connect();
-            }
-            KeyCode::Esc => {
-                self.exit = true;
-            }
-            _ => {}
-        }
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
     }
 
-    fn handle_input(&mut self, event: KeyEvent){
-        match event.code {
-            KeyCode::Char('q') => {
-                self.exit=true;
-            }
-            KeyCode::Char('+') => {
-                self.increase_volume();
-            }
-            KeyCode::Char('-') => {
-                self.decrease_volume();
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+impl AudioOutput for RodioOutput {
+    fn load(&mut self, song: Song) -> Result<Option<Duration>, ClientError> {
+        let playing = !self.sink.is_paused() && !self.sink.empty();
+        let fresh = (playing && !self.fade.is_zero()).then(|| Sink::try_new(&self.handle).ok()).flatten();
+        // The old song fades out on a sink of its own, so the new one can wait paused meanwhile.
+        match fresh {
+            Some(sink) => {
+                sink.set_volume(self.sink.volume());
+                self.fade_out.store(true, Ordering::Relaxed);
+                self.fading = Some(std::mem::replace(&mut self.sink, sink));
             }
-            _ => {}
+            _ => self.sink.stop()
         }
+        self.fade_out = Arc::new(AtomicBool::new(false));
+
+        let duration = match song {
+            Song::Memory(data, format) => self.append(decode(Cursor::new(data), format.as_deref())?),
+            Song::File(path) => {
+                let format = path.extension().and_then(|ext| ext.to_str());
+                self.append(decode(BufReader::new(File::open(&path)?), format)?)
+            }
+        };
+        self.sink.pause();
+        Ok(duration)
     }
-    fn connect(&mut self) {
-        if let Ok(mut stream) = TcpStream::connect(self.connection_string.as_str()) {
-            self.state = AppState::Paused;
-            let sender = self.event_sender.clone();
-            
-            self.send_nickname(&mut stream);
-
-            self.stream = Some(thread::spawn(move || {
-                loop {
-                    let command = read_command(&mut stream).unwrap();
-                    let mut event = AppEvent::Command(command.clone());
-
-                    if let Command::Transfer = command {
-                        let song = read_data(&mut stream).unwrap();
-                        event = AppEvent::SongData(song);
-                    }
-                    sender.send(event).unwrap();
-                }
-            }));
-        }
-        else {
-            self.connection_string.clear();
-        }
+
+    fn position(&self) -> Duration {
+        self.sink.get_pos()
     }
-    fn send_nickname(&mut self, stream: &mut TcpStream) {
-        let bytes = self.nickname.as_bytes();
-        let num_bytes_numeric = bytes.len() as u64;
-        let num_bytes = num_bytes_numeric.to_be_bytes();
 
-        stream.write_all(&num_bytes).unwrap();
-        stream.write_all(bytes).unwrap();
+    fn level(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::Relaxed))
     }
 
-    fn append_song(&mut self, song: Vec<u8>) -> Result<(),Box<dyn Error>> {
-        self.sink.stop();
-        let decoder = Decoder::new(Cursor::new(song))?;
-        self.sink.append(decoder);
-        self.sink.pause();
-        self.state = AppState::Paused;
-        Ok(())
+    fn seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.sink.try_seek(position)
     }
 
     fn play(&mut self) {
-        self.state = AppState::Playing;
         self.sink.play();
     }
 
     fn pause(&mut self) {
-        self.state = AppState::Paused;
         self.sink.pause();
     }
 
-    fn increase_volume(&mut self) {
-        self.volume += 0.05;
-
-        if self.volume > 1.0 {
-            self.volume = 1.0;
-        }
-
-        self.sink.set_volume(self.volume);
+    fn set_volume(&mut self, volume: f32) {
+        self.sink.set_volume(volume);
     }
 
-    fn decrease_volume(&mut self) {
-        self.volume -= 0.05;
+    fn is_finished(&self) -> bool {
+        self.sink.empty()
+    }
 
-        if self.volume < 0.0 {
-            self.volume = 0.0;
-        }
+    fn play_tone(&mut self, tone: Tone, volume: f32) {
+        let _ = self.handle.play_raw(tone.amplify(volume));
+    }
+}
 
-        self.sink.set_volume(self.volume);
+/// Picks the decoder from the file extension, unknown formats are left to rodio to detect.
+fn decode<R: Read + Seek + Send + Sync + 'static>(data: R, format: Option<&str>) -> Result<Decoder<R>, DecoderError> {
+    match format {
+        Some("mp3") => Decoder::new_mp3(data),
+        Some("flac") => Decoder::new_flac(data),
+        Some("ogg") => Decoder::new_vorbis(data),
+        Some("wav") => Decoder::new_wav(data),
+        _ => Decoder::new(data)
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+/// A short synthesized sine tone, used to check the audio setup without a song.
+struct Tone {
+    frequency: f32,
+    sample_rate: u32,
+    num_sample: u32,
+    delay_samples: u32,
+    total_samples: u32
+}
 
-    let mut terminal = ratatui::init();
+impl Tone {
+    fn new(frequency: f32, duration: Duration) -> Tone {
+        let sample_rate = 48000;
+        Tone {
+            frequency,
+            sample_rate,
+            num_sample: 0,
+            delay_samples: 0,
+            total_samples: (duration.as_secs_f32() * sample_rate as f32) as u32
+        }
+    }
 
-    let (_audio_stream, handle) = OutputStream::try_default()?;
-    let sink = Sink::try_new(&handle)?;
-    sink.set_volume(0.5);
+    /// Starts the tone after `delay` of silence, so several tones can form a melody.
+    fn delayed(mut self, delay: Duration) -> Tone {
+        self.delay_samples = (delay.as_secs_f32() * self.sample_rate as f32) as u32;
+        self.total_samples += self.delay_samples;
+        self
+    }
+}
 
-    let (tx, rx) = mpsc::channel::<AppEvent>();
+impl Iterator for Tone {
+    type Item = f32;
 
-    let t1 = tx.clone();
-    let t2 = tx.clone();
+    fn next(&mut self) -> Option<f32> {
+        if self.num_sample >= self.total_samples {
+            return None;
+        }
 
-    thread::spawn(move || loop {
-        let event = event::read().unwrap();
-        t2.send(AppEvent::CrossTerm(event)).unwrap();
-    });
+        let num_sample = self.num_sample;
+        self.num_sample += 1;
 
-    App{
-        connection_string: String::new(),
-        nickname: String::new(),
-        state: AppState::EnterNickname,
-        event_loop: rx,
-        stream: None,
-        event_sender: t1,
-        current_song: None,
-        sink,
-        volume: 0.5,
-        exit: false,
-    }.run(&mut terminal)?;
+        if num_sample < self.delay_samples {
+            return Some(0.0);
+        }
 
-    ratatui::restore();
-    Ok(())
+        let time = (num_sample - self.delay_samples) as f32 / self.sample_rate as f32;
+        Some((TAU * self.frequency * time).sin())
+    }
 }
 
-fn read_command(stream: &mut TcpStream) -> Result<Command, Box<dyn Error>> {
-    let mut bytes = [0_u8; 1];
-    stream.read_exact(&mut bytes)?;
+impl Source for Tone {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some((self.total_samples - self.num_sample) as usize)
+    }
 
-    let numeric = u8::from_be_bytes(bytes);
+    fn channels(&self) -> u16 {
+        1
+    }
 
-    match numeric {
-        1 => Ok(Command::Play),
-        2 => Ok(Command::Transfer),
-        3 => Ok(Command::Pause),
-        4 => Ok(Command::Repeat),
-        _ => Err(Box::new(io::Error::new(ErrorKind::Other, "Invalid Command")))
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f32(self.total_samples as f32 / self.sample_rate as f32))
     }
 }
 
-fn read_data(stream: &mut TcpStream) -> Result<Vec<u8>, Box<dyn Error>> {
-    let mut bytes_to_read = [0_u8; 64 / 8];
-    stream.read_exact(&mut bytes_to_read)?;
-    let bytes = u64::from_be_bytes(bytes_to_read);
+struct NickNamePopup{
+    nickname: String
+}
+
+impl Widget for NickNamePopup {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered().title(" Enter Nickname ");
+        Paragraph::new(vec![
+            Line::from(vec![
+                self.nickname.as_str().gray().bold()
+            ])
+        ]).block(block).gray().render(area, buf);
+    }
+}
 
-    //println!("Server told me to revieve {} bytes", bytes);
+struct GuessPopup{
+    guess: String
+}
 
-    let mut data = vec![0_u8; bytes as usize];
-    stream.read_exact(&mut data)?;
+impl Widget for GuessPopup {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered().title(" Your Guess ");
+        Paragraph::new(vec![
+            Line::from(vec![
+                self.guess.as_str().gray().bold()
+            ]),
+            Line::from(vec![]),
+            Line::from(vec!["[Enter] send  [Esc] cancel".dark_gray()])
+        ]).block(block).gray().render(area, buf);
+    }
+}
 
-    //println!("I have read the data!");
-    Ok(data)
+struct SummaryPopup{
+    summary: Option<Summary>
 }
 
-fn popup_area(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
-    let vertical = Layout::vertical([Constraint::Percentage(percent_y)]).flex(Flex::Center);
-    let horizontal = Layout::horizontal([Constraint::Percentage(percent_x)]).flex(Flex::Center);
-    let [area] = vertical.areas(area);
-    let [area] = horizontal.areas(area);
-    area
+impl Widget for SummaryPopup {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered().title(" Game Over ");
+        let lines = match self.summary {
+            Some(summary) => vec![
+                Line::from(vec![
+                    "Your score: ".into(),
+                    format!("{} / {}", summary.points, summary.possible).yellow().bold()
+                ]),
+                Line::from(vec![
+                    "Songs: ".into(),
+                    summary.songs.to_string().cyan().bold()
+                ])
+            ],
+            None => vec![Line::from(vec!["The host ended the quiz.".into()])]
+        };
+        Paragraph::new(lines).block(block).gray().render(area, buf);
+    }
+}
+
+struct TitleGradingPopup{
+    grading: Option<TitleGrading>,
+    style: GradeStyle,
+    detailed: bool,
+    countdown: Option<Duration>
+}
+
+fn grading_span(grade: Option<bool>, style: GradeStyle, points: u32) -> Span<'static> {
+    match (style, grade) {
+        (_, None) => "not graded".gray().bold(),
+        (GradeStyle::Words, Some(true)) => "correct".green().bold(),
+        (GradeStyle::Words, Some(false)) => "incorrect".red().bold(),
+        (GradeStyle::Stars, Some(true)) => "★".green().bold(),
+        (GradeStyle::Stars, Some(false)) => "☆".red().bold(),
+        (GradeStyle::Points, Some(true)) => format!("+{}", points).green().bold(),
+        (GradeStyle::Points, Some(false)) => "+0".red().bold()
+    }
+}
+
+impl Widget for TitleGradingPopup {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered().title(" Reveal ");
+
+        if let Some(left) = self.countdown {
+            let line = Line::from(vec![
+                format!("The answer appears in {}s...", left.as_secs() + 1).yellow().bold()
+            ]);
+            Paragraph::new(line).block(block).gray().render(area, buf);
+            return;
+        }
+
+        let lines = match self.grading {
+            Some(grading) if self.detailed => {
+                let mut lines = vec![
+                    Line::from(vec!["Correct answer".bold()]),
+                    Line::from(vec!["Title: ".blue().bold(), grading.title.into()]),
+                    Line::from(vec!["Interpret: ".yellow().bold(), grading.interpret.into()]),
+                    Line::from(vec![]),
+                    Line::from(vec![
+                        "Title graded: ".blue().bold(),
+                        grading_span(grading.title_correct, self.style, grading.title_points)
+                    ]),
+                    Line::from(vec![
+                        "Interpret graded: ".yellow().bold(),
+                        grading_span(grading.interpret_correct, self.style, grading.interpret_points)
+                    ])
+                ];
+                if grading.title_correct.is_none() || grading.interpret_correct.is_none() {
+                    lines.push(Line::from(vec![
+                        "The host has not finished grading this song".gray().italic()
+                    ]));
+                }
+                lines
+            }
+            Some(grading) => vec![
+                Line::from(vec![
+                    "Title: ".blue().bold(),
+                    grading.title.into(),
+                    " - ".into(),
+                    grading_span(grading.title_correct, self.style, grading.title_points)
+                ]),
+                Line::from(vec![
+                    "Interpret: ".yellow().bold(),
+                    grading.interpret.into(),
+                    " - ".into(),
+                    grading_span(grading.interpret_correct, self.style, grading.interpret_points)
+                ])
+            ],
+            None => vec![
+                Line::from(vec![
+                    "Waiting for the answer...".gray().italic()
+                ])
+            ]
+        };
+
+        Paragraph::new(lines).block(block).gray().render(area, buf);
+    }
+}
+
+struct AnswerPopup {
+    answer: Option<Answer>
+}
+
+impl Widget for AnswerPopup {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered().title(" Answer ");
+
+        let lines = match self.answer {
+            Some(answer) => vec![
+                Line::from(vec!["Title: ".bold(), answer.title.into()]),
+                Line::from(vec!["Interpret: ".bold(), answer.interpret.into()])
+            ],
+            None => vec![Line::from(vec!["Waiting for the answer...".gray().italic()])]
+        };
+
+        Paragraph::new(lines).block(block).gray().render(area, buf);
+    }
+}
+
+struct HostMirror {
+    state: HostState
+}
+
+fn format_secs(secs: u64) -> String {
+    format!("{:02}:{:02}", secs / 60, secs % 60)
+}
+
+impl Widget for HostMirror {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered().title(" Host Screen ");
+        let state = self.state;
+
+        let mut lines = vec![
+            Line::from(vec![
+                "Song: ".bold(),
+                format!("{} / {}", state.song, state.total).yellow().bold()
+            ]),
+            Line::from(vec![
+                "Titles: ".blue().bold(),
+                state.titles_correct.to_string().green().bold(),
+                "  Interprets: ".yellow().bold(),
+                state.interprets_correct.to_string().green().bold(),
+                "  Disputed: ".magenta().bold(),
+                state.disputed.to_string().into()
+            ]),
+            Line::from(vec![
+                "Points: ".bold(),
+                state.points.to_string().yellow().bold()
+            ]),
+            Line::from(vec![
+                "Elapsed: ".bold(),
+                format_secs(state.elapsed_secs).into(),
+                match state.time_left_secs {
+                    None => "".into(),
+                    Some(left) => format!(" ({} left)", format_secs(left)).into()
+                }
+            ]),
+            Line::from(vec![])
+        ];
+
+        lines.push(match state.answer {
+            Some((title, interpret)) => Line::from(vec![
+                "Answer: ".bold(),
+                title.blue().bold(),
+                " - ".into(),
+                interpret.yellow().bold()
+            ]),
+            None => Line::from(vec!["Answer hidden until the reveal".gray().italic()])
+        });
+
+        lines.push(Line::from(vec![]));
+        lines.push(Line::from(vec![
+            "Players: ".bold(),
+            state.players.join(", ").into()
+        ]));
+
+        if let Some(reason) = state.game_over {
+            lines.push(Line::from(vec![]));
+            lines.push(Line::from(vec![format!("GAME OVER - {}", reason).red().bold()]));
+        }
+
+        Paragraph::new(lines).block(block).render(area, buf);
+    }
+}
+
+struct ServerPopup{
+    url: String,
+    message: Option<String>
+}
+
+impl Widget for ServerPopup {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered().title(" Enter Server Url ");
+        let mut lines = vec![
+            Line::from(vec![
+                self.url.as_str().gray().bold()
+            ])
+        ];
+
+        if let Some(message) = self.message {
+            lines.push(Line::from(vec![message.red()]));
+        }
+
+        Paragraph::new(lines).block(block).gray().render(area, buf);
+    }
+}
+
+impl App {
+    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), ClientError> {
+        while !self.exit {
+            terminal.draw(|frame| self.draw(frame))?;
+            self.handle_events(terminal)?
+        }
+        Ok(())
+    }
+    fn draw(&self, frame: &mut Frame) {
+        let area = frame.area();
+
+        let layout = Layout::vertical(vec![
+            Constraint::Percentage(80),
+            Constraint::Fill(1)
+        ]).split(area);
+
+        let show_popup = self.guess.is_some() || (self.summary.is_some() && self.in_game()) || matches!(self.state, AppState::EnterNickname | AppState::Disconnected | AppState::Revealing | AppState::ShowingAnswer | AppState::Finished);
+
+        let block = Block::bordered().title(" Music Quiz Client ");
+        let mut lines = vec![
+            Line::from(vec![
+                "Nickname: ".into(),
+                self.nickname.clone().yellow().bold()
+            ]),
+            Line::from(vec![
+                "Server url: ".into(),
+                self.connection_string.clone().blue().bold()
+            ]),
+            Line::from(vec![
+                "Status: ".into(),
+                if self.loading {
+                    "LOADING SONG...".yellow().bold()
+                } else {
+                    format!("{}", &self.state).green().bold()
+                }
+            ])
+        ];
+
+        if let Some(solo) = &self.solo {
+            let name = solo.songs[solo.index].file_name().unwrap_or_default().to_string_lossy().to_string();
+            lines.push(Line::from(vec!["Solo: ".into(), name.cyan().bold()]));
+            if let Some(message) = &self.status_message {
+                lines.push(Line::from(vec![message.as_str().red()]));
+            }
+        }
+
+        if let Some(name) = &self.quiz_name {
+            lines.insert(0, Line::from(vec![
+                "Connected to: ".into(),
+                name.as_str().cyan().bold()
+            ]));
+        }
+
+        if let Some((song, total)) = self.song_position {
+            lines.push(Line::from(vec![
+                "Song: ".into(),
+                format!("{} / {}", song, total).yellow().bold()
+            ]));
+        }
+
+        if self.buzzed {
+            lines.push(Line::from(vec!["BUZZED IN".red().bold()]));
+        }
+
+        if let Some(warning) = &self.input_warning {
+            lines.push(Line::from(vec![warning.as_str().red().bold()]));
+        }
+
+        if let Some(motd) = &self.motd {
+            lines.push(Line::from(vec![]));
+            lines.push(Line::from(vec![motd.as_str().magenta().bold()]));
+        }
+
+        if !self.hints.is_empty() {
+            lines.push(Line::from(vec![]));
+            for (index, hint) in self.hints.iter().enumerate() {
+                lines.push(Line::from(vec![
+                    format!("Clue {}: ", index + 1).cyan().bold(),
+                    hint.as_str().into()
+                ]));
+            }
+        }
+
+        match &self.host_state {
+            Some(state) if self.mirror => {
+                frame.render_widget(HostMirror{state: state.clone()}, layout[0]);
+            }
+            _ if self.ambiance => {
+                let glow = (self.energy.clamp(0.0, 1.0) * 120.0) as u8;
+                Paragraph::new(lines).block(block).bg(Color::Rgb(glow / 2, glow / 4, glow)).render(layout[0], frame.buffer_mut())
+            }
+            _ => Paragraph::new(lines).block(block).render(layout[0], frame.buffer_mut())
+        }
+
+        let [audio_area, progress_area] = Layout::horizontal([Constraint::Percentage(50), Constraint::Fill(1)]).areas(layout[1]);
+
+        let audio_block = Block::bordered().title(" Audio Level ");
+        let gauge = Gauge::default().block(audio_block).percent((self.volume * 100.0).round() as u16);
+        match self.muted {
+            Some(_) => gauge.label("MUTED").render(audio_area, frame.buffer_mut()),
+            None => gauge.render(audio_area, frame.buffer_mut())
+        }
+
+        let (ratio, label) = match self.song_duration.filter(|total| !total.is_zero()) {
+            Some(total) => (
+                (self.song_elapsed.as_secs_f64() / total.as_secs_f64()).min(1.0),
+                format!("{} / {}", format_duration(self.song_elapsed), format_duration(total))
+            ),
+            None => (0.0, format_duration(self.song_elapsed))
+        };
+        let progress_area = match self.countdown {
+            Some((left, total)) => {
+                let [progress_area, countdown_area] = Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(progress_area);
+                let ratio = if total == 0 { 0.0 } else { (left as f64 / total as f64).min(1.0) };
+                let (label, color) = match left {
+                    0 => ("TIME'S UP".to_string(), Color::Red),
+                    1..=5 => (format!("{}s left", left), Color::Red),
+                    _ => (format!("{}s left", left), Color::Yellow)
+                };
+                let countdown_block = Block::bordered().title(" Countdown ");
+                Gauge::default().block(countdown_block).gauge_style(color).ratio(ratio).label(label).render(countdown_area, frame.buffer_mut());
+                progress_area
+            }
+            None => progress_area
+        };
+        let progress_block = Block::bordered().title(" Song Progress ");
+        Gauge::default().block(progress_block).ratio(ratio).label(label).render(progress_area, frame.buffer_mut());
+
+        if show_popup {
+            let height = if self.detailed_reveal && matches!(self.state, AppState::Revealing) { 40 } else { 20 };
+            let area = popup_area(area, 60, height);
+            frame.render_widget(Clear, area); //this clears out the background
+
+            match self.state {
+                _ if self.guess.is_some() => {
+                    frame.render_widget(GuessPopup{guess: self.guess.clone().unwrap_or_default()}, area);
+                }
+                _ if self.summary.is_some() && self.in_game() => {
+                    frame.render_widget(SummaryPopup{summary: self.summary.clone()}, area);
+                }
+                AppState::EnterNickname => {
+                    frame.render_widget(NickNamePopup{nickname: self.nickname.clone()}, area);
+                }
+                AppState::Disconnected => {
+                    frame.render_widget(ServerPopup{url: self.connection_string.clone(), message: self.status_message.clone()}, area);
+                }
+                AppState::Revealing => {
+                    frame.render_widget(TitleGradingPopup{
+                        grading: self.current_title_grading.clone(),
+                        style: self.grade_style,
+                        detailed: self.detailed_reveal,
+                        countdown: self.reveal_at
+                            .map(|at| at.saturating_duration_since(Instant::now()))
+                            .filter(|left| !left.is_zero())
+                    }, area);
+                }
+                AppState::Finished => {
+                    frame.render_widget(SummaryPopup{summary: self.summary.clone()}, area);
+                }
+                AppState::ShowingAnswer => {
+                    frame.render_widget(AnswerPopup{answer: self.current_answer.clone()}, area);
+                }
+                _ => {}
+            }
+        }
+    }
+    fn handle_events(&mut self, terminal: &mut DefaultTerminal) -> Result<(), ClientError> {
+        let event = self.event_loop.recv()?;
+        if matches!(event, AppEvent::SongData(_)) && self.in_game() {
+            // Decoding can take a moment, show that before it starts.
+            self.loading = true;
+            terminal.draw(|frame| self.draw(frame))?;
+        }
+        self.update(event);
+        Ok(())
+    }
+
+    /// Whether songs and reveals make sense right now. Outside a game they can only come
+    /// from an out of order message and are dropped.
+    fn in_game(&self) -> bool {
+        !matches!(self.state, AppState::EnterNickname | AppState::Disconnected | AppState::Reconnecting(_) | AppState::Queued(_) | AppState::Finished)
+    }
+    fn update(&mut self, event: AppEvent) {
+        match event {
+            AppEvent::SongData(_) | AppEvent::TitleGrading(_) if !self.in_game() => {
+                warn!("Ignoring a song or grading that arrived outside a game ({})", self.state);
+            }
+            AppEvent::Command(cmd) => {
+                match cmd {
+                    Command::Play => { self.play() }
+                    Command::Transfer | Command::Motd | Command::PlayState | Command::Hint | Command::HostState | Command::Resync | Command::QuizName | Command::ShowAnswer | Command::Queued | Command::SeekForward | Command::SeekBack | Command::Summary | Command::Countdown | Command::PreloadStart | Command::PreloadChunk => { /*Should not happen TM*/ }
+                    Command::Pause => { self.pause() }
+                    Command::Reveal => {
+                        self.state = AppState::Revealing;
+                        self.schedule_reveal_dismiss();
+                    }
+                    Command::SwapPreloaded => { self.swap_preloaded() }
+                    Command::KeepSong => { self.keep_song() }
+                    Command::Shutdown => {
+                        self.audio.pause();
+                        self.guess = None;
+                        self.state = AppState::Finished;
+                    }
+                    Command::Chime => { self.play_chime() }
+                    Command::Repeat => {
+                        if let Some(song) = self.current_song.clone() {
+                            if let Err(err) = self.append_song(song) {
+                                error!("Could not replay the song: {}", err);
+                                self.status_message = Some(err.to_string());
+                            }
+                        }
+                    }
+                }
+            }
+            AppEvent::Motd(motd) => {
+                self.motd = Some(motd);
+            }
+            AppEvent::QuizName(name) => {
+                self.quiz_name = Some(name);
+            }
+            AppEvent::Queued(0) => {
+                self.state = AppState::Paused;
+            }
+            AppEvent::Queued(position) => {
+                self.state = AppState::Queued(position);
+            }
+            AppEvent::Seek(forward, step) => self.seek(forward, step),
+            AppEvent::PreloadStart(format, size) => {
+                // Too big to hold in memory, the song is then asked for when it is needed.
+                self.next_song = (size <= MAX_IN_MEMORY_PAYLOAD).then(|| Preload { data: Vec::new(), format, size });
+            }
+            AppEvent::PreloadChunk(chunk) => {
+                if let Some(next) = self.next_song.as_mut() {
+                    next.data.extend_from_slice(&chunk);
+                    if next.data.len() as u64 > next.size {
+                        self.next_song = None;
+                    }
+                }
+            }
+            AppEvent::PlayAt(_) => self.play(),
+            AppEvent::SongPosition(song, total) => self.song_position = Some((song, total)),
+            AppEvent::Countdown(left, total) => {
+                self.countdown = Some((left, total));
+                if left == 0 && matches!(self.state, AppState::Playing) {
+                    self.pause();
+                }
+            }
+            AppEvent::Answer(answer) => {
+                self.current_answer = Some(answer);
+                self.state = AppState::ShowingAnswer;
+            }
+            AppEvent::TitleGrading(grading) => {
+                self.reveal_at = (grading.reveal_delay > 0)
+                    .then(|| Instant::now() + Duration::from_secs(grading.reveal_delay));
+                self.current_title_grading = Some(grading);
+                self.reveal_generation += 1;
+            }
+            AppEvent::Hint(hint) => {
+                self.hints.push(hint);
+            }
+            AppEvent::Summary(summary) => {
+                self.summary = Some(summary);
+            }
+            AppEvent::HostState(state) => {
+                self.host_state = Some(state);
+            }
+            AppEvent::Resync(resync) => self.resync(resync),
+            AppEvent::SongData(song) => {
+                self.buzzed = false;
+                self.countdown = None;
+                self.current_title_grading = None;
+                self.current_answer = None;
+                self.hints.clear();
+                if let Some(Song::File(previous)) = self.current_song.replace(song.clone()) {
+                    // A kept song comes back as the same file.
+                    if !matches!(&song, Song::File(path) if *path == previous) {
+                        let _ = fs::remove_file(previous);
+                    }
+                }
+                if let Err(err) = self.append_song(song) {
+                    error!("Could not load the song: {}", err);
+                    self.status_message = Some(err.to_string());
+                }
+                self.loading = false;
+            }
+            // Left on purpose or the quiz is over, either way there is nothing to reconnect to.
+            AppEvent::Disconnected if matches!(self.state, AppState::Disconnected | AppState::Finished) => {}
+            AppEvent::Disconnected => {
+                warn!("Lost the connection to {}", self.connection_string);
+                self.audio.pause();
+                self.connection = None;
+                self.stream = None;
+
+                if self.reconnect_attempts > 0 {
+                    self.state = AppState::Reconnecting(1);
+                    self.schedule_reconnect(1);
+                } else {
+                    self.state = AppState::Disconnected;
+                }
+            }
+            AppEvent::PlayState(playing) => self.reconcile_play_state(playing),
+            AppEvent::Tick => {
+                if matches!(self.state, AppState::Playing) {
+                    self.song_elapsed = self.audio.position();
+                }
+                if self.ambiance {
+                    let level = if matches!(self.state, AppState::Playing) { self.audio.level() } else { 0.0 };
+                    self.energy += (level - self.energy) * AMBIANCE_SMOOTHING;
+                }
+                if matches!(self.state, AppState::Playing) && self.audio.is_finished() {
+                    self.state = AppState::PlaybackFinished;
+                }
+                if self.reveal_at.is_some_and(|at| at <= Instant::now()) {
+                    self.reveal_at = None;
+                }
+                if self.last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+                    self.last_heartbeat = Instant::now();
+                    self.send_request(Request::Heartbeat);
+                }
+            }
+            AppEvent::DismissReveal(generation) => {
+                if generation == self.reveal_generation && matches!(self.state, AppState::Revealing) {
+                    self.pause();
+                }
+            }
+            AppEvent::InputError(err) => {
+                self.input_warning = Some(format!("Keyboard input failing, retrying: {}", err));
+            }
+            AppEvent::InputRecovered => {
+                self.input_warning = None;
+            }
+            AppEvent::InputLost => {
+                self.input_warning = Some("Keyboard input lost! The song plays on, restart to regain control.".to_string());
+            }
+            AppEvent::Reconnect => {
+                if let AppState::Reconnecting(attempt) = self.state {
+                    if self.open_connection().is_ok() {
+                        self.status_message = None;
+                    } else if attempt >= self.reconnect_attempts {
+                        self.status_message = Some(format!("Server lost, gave up after {} attempts", attempt));
+                        self.state = AppState::Disconnected;
+                    } else {
+                        self.state = AppState::Reconnecting(attempt + 1);
+                        self.schedule_reconnect(attempt + 1);
+                    }
+                }
+            }
+            AppEvent::CrossTerm(event) => match event {
+                Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
+                    match self.state {
+                        _ if self.guess.is_some() => { self.handle_guess_input(key_event); }
+                        _ if self.solo.is_some() => { self.handle_solo_input(key_event); }
+                        AppState::EnterNickname => { self.handle_nickname_input(key_event); }
+                        AppState::Disconnected => { self.handle_url_input(key_event); }
+                        _ => { self.handle_input(key_event); }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_solo_input(&mut self, event: KeyEvent) {
+        match event.code {
+            KeyCode::Char('p') | KeyCode::Char(' ') => {
+                if matches!(self.state, AppState::Playing) { self.pause() } else { self.play() }
+            }
+            KeyCode::Char('n') => {
+                let next = self.solo.as_ref().map_or(0, |solo| (solo.index + 1) % solo.songs.len());
+                self.load_solo_song(next);
+            }
+            KeyCode::Esc => {
+                self.exit = true;
+            }
+            // Only the keys that work without a server.
+            KeyCode::Char('q' | '+' | '-' | ']' | '[' | 'm' | 'b') => self.handle_input(event),
+            _ => {}
+        }
+    }
+
+    /// Loads the solo song at `index`, paused at its start.
+    fn load_solo_song(&mut self, index: usize) {
+        let Some(solo) = self.solo.as_mut() else {
+            return;
+        };
+        let Some(path) = solo.songs.get(index).cloned() else {
+            return;
+        };
+        solo.index = index;
+        self.song_position = Some((index + 1, solo.songs.len()));
+
+        // Not kept as the current song, that one gets deleted once replaced.
+        self.status_message = None;
+        if let Err(err) = self.append_song(Song::File(path.clone())) {
+            // Still out of the prompts, so the next song can be tried.
+            self.state = AppState::Paused;
+            self.status_message = Some(format!("Could not play {}: {}", path.display(), err));
+        }
+    }
+
+    fn handle_nickname_input(&mut self, event: KeyEvent) {
+        match event.code {
+            KeyCode::Char(new) => {
+                self.nickname.push(new);
+            }
+            KeyCode::Backspace => {
+                self.nickname.pop();
+            }
+            KeyCode::Enter => {
+                self.state = AppState::Disconnected;
+            }
+            KeyCode::Esc => {
+                self.exit = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_guess_input(&mut self, event: KeyEvent) {
+        let Some(guess) = self.guess.as_mut() else {
+            return;
+        };
+        match event.code {
+            KeyCode::Char(new) => {
+                guess.push(new);
+            }
+            KeyCode::Backspace => {
+                guess.pop();
+            }
+            KeyCode::Enter => {
+                let guess = self.guess.take().unwrap_or_default();
+                if !guess.trim().is_empty() {
+                    self.send_guess(guess.trim());
+                }
+            }
+            KeyCode::Esc => {
+                self.guess = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_url_input(&mut self, event: KeyEvent) {
+        match event.code {
+            KeyCode::Char(new) => {
+                self.connection_string.push(new);
+            }
+            KeyCode::Backspace => {
+                self.connection_string.pop();
+            }
+            KeyCode::Enter => {
+                self.connect();
+            }
+            KeyCode::Esc => {
+                self.exit = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_input(&mut self, event: KeyEvent){
+        match event.code {
+            KeyCode::Char('q') => {
+                self.send_request(Request::Leave);
+                self.exit=true;
+            }
+            KeyCode::Char('+') => {
+                self.increase_volume(VOLUME_STEP);
+            }
+            KeyCode::Char('-') => {
+                self.decrease_volume(VOLUME_STEP);
+            }
+            KeyCode::Char(']') => {
+                self.increase_volume(FINE_VOLUME_STEP);
+            }
+            KeyCode::Char('[') => {
+                self.decrease_volume(FINE_VOLUME_STEP);
+            }
+            KeyCode::Char('m') => {
+                self.toggle_mute();
+            }
+            KeyCode::Char('r') => {
+                self.send_request(Request::Resend);
+            }
+            KeyCode::Char('b') => {
+                self.play_test_tone();
+            }
+            KeyCode::Char(' ') => {
+                self.buzz();
+            }
+            KeyCode::Char('g') => {
+                self.start_guess();
+            }
+            KeyCode::Char('d') => {
+                self.leave_server();
+            }
+            KeyCode::Esc if matches!(self.state, AppState::Reconnecting(_)) => {
+                // A reconnect already scheduled finds the state changed and does nothing.
+                self.status_message = Some("Stopped reconnecting".to_string());
+                self.state = AppState::Disconnected;
+            }
+            _ => {}
+        }
+    }
+    /// Leaves the server and goes back to the address prompt, the nickname is kept.
+    fn leave_server(&mut self) {
+        self.send_request(Request::Leave);
+        if let Some(connection) = self.connection.take() {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+        // Its Disconnected event is queued before any key typed into the prompt.
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.join();
+        }
+
+        self.audio.pause();
+        if let Some(Song::File(previous)) = self.current_song.take() {
+            let _ = fs::remove_file(previous);
+        }
+        self.current_title_grading = None;
+        self.current_answer = None;
+        self.reveal_at = None;
+        self.hints.clear();
+        self.host_state = None;
+        self.song_position = None;
+        self.song_duration = None;
+        self.song_elapsed = Duration::ZERO;
+        self.motd = None;
+        self.quiz_name = None;
+        self.summary = None;
+        self.buzzed = false;
+        self.guess = None;
+        self.status_message = None;
+        self.connection_string.clear();
+        self.state = AppState::Disconnected;
+    }
+    fn connect(&mut self) {
+        // The address stays in the prompt so a typo can be fixed.
+        self.status_message = self.open_connection().err().map(|err| err.to_string());
+    }
+    /// Joins right away when the command line named the server and the nickname.
+    /// A failed attempt leaves the address prompt open as usual.
+    fn connect_on_start(&mut self, server: &str) {
+        if server.is_empty() || !matches!(self.state, AppState::Disconnected) {
+            return;
+        }
+        self.connect();
+    }
+    fn open_connection(&mut self) -> Result<(), ClientError> {
+        info!("Connecting to {}", self.connection_string);
+        let mut stream = match connect_to(&self.connection_string) {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("{}", err);
+                return Err(err);
+            }
+        };
+        if let Err(err) = self.send_nickname(&mut stream) {
+            error!("Could not send the handshake to {}: {}", self.connection_string, err);
+            return Err(err);
+        }
+        info!("Connected to {} as {}", self.connection_string, self.nickname);
+
+        self.state = AppState::Paused;
+        let sender = self.event_sender.clone();
+        self.connection = stream.try_clone().ok();
+
+        let song_dir = self.song_dir.clone();
+        let timeout = self.read_timeout;
+        self.stream = Some(thread::spawn(move || stream_handler(stream, sender, song_dir, timeout)));
+        Ok(())
+    }
+    fn schedule_reconnect(&self, attempt: u32) {
+        let sender = self.event_sender.clone();
+        let delay = reconnect_delay(attempt);
+        thread::spawn(move || {
+            thread::sleep(delay);
+            let _ = sender.send(AppEvent::Reconnect);
+        });
+    }
+    /// Sends a dismiss for the current reveal once the timeout has passed after the answer shows.
+    fn schedule_reveal_dismiss(&mut self) {
+        let Some(timeout) = self.reveal_timeout else { return };
+        self.reveal_generation += 1;
+        let generation = self.reveal_generation;
+        let delay = timeout + self.reveal_at.map_or(Duration::ZERO, |at| at.saturating_duration_since(Instant::now()));
+        let sender = self.event_sender.clone();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            let _ = sender.send(AppEvent::DismissReveal(generation));
+        });
+    }
+    fn send_nickname(&mut self, stream: &mut TcpStream) -> Result<(), ClientError> {
+        let handshake = Handshake {
+            nickname: &self.nickname,
+            color: self.color.as_deref(),
+            mirror: self.mirror,
+            song_hash: self.current_song.as_ref().and_then(|song| song.hash().ok())
+        };
+        let json = serde_json::to_string(&handshake).map_err(io::Error::from)?;
+        let bytes = json.as_bytes();
+        let num_bytes_numeric = bytes.len() as u64;
+        let num_bytes = num_bytes_numeric.to_be_bytes();
+
+        stream.write_all(&num_bytes)?;
+        stream.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn send_request(&mut self, request: Request) {
+        if let Some(connection) = self.connection.as_mut() {
+            if connection.write_all(&request.code().to_be_bytes()).is_err() {
+                self.connection = None;
+            }
+        }
+    }
+
+    fn start_guess(&mut self) {
+        if !self.mirror && self.in_game() && self.current_song.is_some() {
+            self.guess = Some(String::new());
+        }
+    }
+
+    /// Starts the held song over as if it was just sent, the server found it unchanged.
+    fn keep_song(&mut self) {
+        match self.current_song.clone() {
+            Some(song) => self.update(AppEvent::SongData(song)),
+            None => self.send_request(Request::Resend)
+        }
+    }
+
+    /// Switches to the preloaded song, or asks for it if the preload was missed.
+    fn swap_preloaded(&mut self) {
+        match self.next_song.take() {
+            Some(next) if next.is_complete() => self.update(AppEvent::SongData(Song::Memory(next.data, next.format))),
+            _ => self.send_request(Request::Resend)
+        }
+    }
+
+    /// Sends a typed guess, the host's grader may grade it right away.
+    fn send_guess(&mut self, guess: &str) {
+        let mut message = vec![Request::Guess.code()];
+        message.extend_from_slice(&(guess.len() as u64).to_be_bytes());
+        message.extend_from_slice(guess.as_bytes());
+        if let Some(connection) = self.connection.as_mut() {
+            if connection.write_all(&message).is_err() {
+                self.connection = None;
+            }
+        }
+    }
+
+    fn append_song(&mut self, song: Song) -> Result<(),ClientError> {
+        self.song_duration = self.audio.load(song)?;
+        self.song_elapsed = Duration::ZERO;
+        self.state = AppState::Ready;
+        Ok(())
+    }
+
+    fn play(&mut self) {
+        self.state = AppState::Playing;
+        self.audio.play();
+    }
+
+    fn pause(&mut self) {
+        self.state = AppState::Paused;
+        self.audio.pause();
+    }
+
+    /// Buzzes in once per song, the host sees who was first.
+    fn buzz(&mut self) {
+        if self.buzzed || self.mirror || self.current_song.is_none() {
+            return;
+        }
+        self.send_request(Request::Buzz);
+        self.buzzed = true;
+    }
+
+    fn seek(&mut self, forward: bool, step: Duration) {
+        if self.current_song.is_none() {
+            return;
+        }
+
+        let position = self.audio.position();
+        let mut target = if forward { position + step } else { position.saturating_sub(step) };
+        if let Some(duration) = self.song_duration {
+            target = target.min(duration);
+        }
+        // Not every format can seek, the song then just keeps playing where it is.
+        if self.audio.seek(target).is_ok() {
+            self.song_elapsed = target;
+        }
+    }
+
+    fn reconcile_play_state(&mut self, playing: bool) {
+        match self.state {
+            AppState::Ready | AppState::Paused if playing && self.current_song.is_some() => self.play(),
+            AppState::Playing if !playing => self.pause(),
+            _ => {}
+        }
+    }
+
+    fn resync(&mut self, resync: Resync) {
+        self.song_position = Some((resync.song, resync.total));
+        self.current_title_grading = None;
+
+        if resync.transfered && self.current_song.is_none() {
+            self.send_request(Request::Resend);
+        }
+
+        if resync.playing && self.current_song.is_some() {
+            self.play();
+        } else {
+            self.pause();
+        }
+    }
+
+    fn play_test_tone(&mut self) {
+        self.audio.play_tone(Tone::new(440.0, Duration::from_millis(500)), self.volume);
+    }
+
+    fn play_chime(&mut self) {
+        self.audio.play_tone(Tone::new(659.25, Duration::from_millis(250)), self.volume);
+        self.audio.play_tone(Tone::new(880.0, Duration::from_millis(400)).delayed(Duration::from_millis(250)), self.volume);
+    }
+
+    fn toggle_mute(&mut self) {
+        match self.muted.take() {
+            Some(volume) => self.volume = volume,
+            None => {
+                self.muted = Some(self.volume);
+                self.volume = 0.0;
+            }
+        }
+
+        self.audio.set_volume(self.volume);
+        info!("{} the sound", if self.muted.is_some() { "Muted" } else { "Unmuted" });
+    }
+
+    /// Unmutes first, so a step starts from the volume before muting.
+    fn increase_volume(&mut self, step: f32) {
+        if let Some(volume) = self.muted.take() {
+            self.volume = volume;
+        }
+        self.volume += step;
+
+        if self.volume > 1.0 {
+            self.volume = 1.0;
+        }
+
+        self.audio.set_volume(self.volume);
+        info!("Volume set to {:.0}%", self.volume * 100.0);
+    }
+
+    fn decrease_volume(&mut self, step: f32) {
+        if let Some(volume) = self.muted.take() {
+            self.volume = volume;
+        }
+        self.volume -= step;
+
+        if self.volume < self.min_volume {
+            self.volume = self.min_volume;
+        }
+
+        self.audio.set_volume(self.volume);
+        info!("Volume set to {:.0}%", self.volume * 100.0);
+    }
+}
+
+fn main() -> Result<(), ClientError> {
+    let args = Args::parse();
+
+    if let Some(path) = &args.log {
+        if let Err(err) = simple_logging::log_to_file(path, LevelFilter::Info) {
+            eprintln!("Could not open log file {}: {}", path.display(), err);
+        }
+    }
+    if let Some(song_dir) = &args.song_dir {
+        fs::create_dir_all(song_dir)?;
+    }
+
+    let solo = match &args.solo {
+        Some(dir) => {
+            let songs = solo_songs(dir)?;
+            if songs.is_empty() {
+                eprintln!("No songs found in {}", dir.display());
+                process::exit(1);
+            }
+            Some(Solo { songs, index: 0 })
+        }
+        None => None
+    };
+
+    let settings_path = settings_path();
+    let min_volume = args.min_volume as f32 / 100.0;
+    let volume = settings_path.as_deref().map_or(DEFAULT_VOLUME, load_volume).max(min_volume);
+
+    let (_audio_stream, handle) = open_output(args.sample_rate)?;
+    let sink = Sink::try_new(&handle)?;
+    sink.set_volume(volume);
+
+    let mut terminal = match init_terminal() {
+        Ok(terminal) => terminal,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let (tx, rx) = mpsc::channel::<AppEvent>();
+
+    let t1 = tx.clone();
+    let t2 = tx.clone();
+
+    let t3 = tx.clone();
+
+    thread::spawn(move || {
+        let mut failures = 0;
+
+        loop {
+            let app_event = match event::read() {
+                Ok(event) => {
+                    if failures > 0 {
+                        info!("Terminal input recovered after {} failures", failures);
+                        failures = 0;
+                        if t2.send(AppEvent::InputRecovered).is_err() {
+                            break;
+                        }
+                    }
+                    AppEvent::CrossTerm(event)
+                }
+                Err(err) => {
+                    failures += 1;
+                    error!("Could not read terminal input ({}): {}", failures, err);
+
+                    if failures >= MAX_INPUT_FAILURES {
+                        let _ = t2.send(AppEvent::InputLost);
+                        break;
+                    }
+
+                    thread::sleep(Duration::from_millis(100 * failures as u64));
+                    AppEvent::InputError(err.to_string())
+                }
+            };
+
+            if t2.send(app_event).is_err() {
+                break;
+            }
+        }
+    });
+
+    thread::spawn(move || loop {
+        thread::sleep(TICK_RATE);
+        if t3.send(AppEvent::Tick).is_err() {
+            break;
+        }
+    });
+
+    let nickname = args.nickname.unwrap_or_default();
+    let state = initial_state(&nickname, args.skip_nickname || args.server.is_some());
+    let server = args.server.unwrap_or_default();
+
+    let mut app = App{
+        connection_string: server.clone(),
+        nickname,
+        motd: None,
+        quiz_name: None,
+        state,
+        event_loop: rx,
+        stream: None,
+        connection: None,
+        event_sender: t1,
+        current_song: None,
+        song_dir: args.song_dir,
+        reconnect_attempts: args.reconnect_attempts,
+        read_timeout: (args.read_timeout > 0).then(|| Duration::from_secs(args.read_timeout)),
+        status_message: None,
+        input_warning: None,
+        current_title_grading: None,
+        current_answer: None,
+        reveal_at: None,
+        reveal_timeout: (args.reveal_timeout > 0).then(|| Duration::from_secs(args.reveal_timeout)),
+        reveal_generation: 0,
+        hints: Vec::new(),
+        grade_style: args.grade_style,
+        detailed_reveal: args.detailed_reveal,
+        color: args.color,
+        mirror: args.mirror,
+        host_state: None,
+        song_position: None,
+        song_duration: None,
+        song_elapsed: Duration::ZERO,
+        last_heartbeat: Instant::now(),
+        audio: Box::new(RodioOutput {
+            sink,
+            handle,
+            level: Arc::new(AtomicU32::new(0)),
+            fade: Duration::from_millis(args.fade),
+            fade_out: Arc::new(AtomicBool::new(false)),
+            fading: None
+        }),
+        volume,
+        min_volume,
+        muted: None,
+        solo,
+        ambiance: args.ambiance,
+        buzzed: false,
+        guess: None,
+        summary: None,
+        countdown: None,
+        next_song: None,
+        energy: 0.0,
+        loading: false,
+        exit: false,
+    };
+    if app.solo.is_some() {
+        app.load_solo_song(0);
+    } else {
+        app.connect_on_start(&server);
+    }
+    let result = app.run(&mut terminal);
+
+    ratatui::restore();
+    if let Some(path) = settings_path {
+        if let Err(err) = save_volume(&path, app.muted.unwrap_or(app.volume)) {
+            eprintln!("Could not save the volume to {}: {}", path.display(), err);
+        }
+    }
+    result
+}
+
+/// Resolves a host:port or [ipv6]:port address and tries every address it resolves to,
+/// a name with an unreachable IPv6 address can still connect over IPv4.
+fn connect_to(address: &str) -> Result<TcpStream, ClientError> {
+    let address = address.trim();
+    let resolved = address.to_socket_addrs().map_err(|err| match err.kind() {
+        io::ErrorKind::InvalidInput => ClientError::InvalidAddress(address.to_string()),
+        _ => ClientError::HostNotFound(address.to_string())
+    })?;
+
+    let mut last_error = None;
+    for socket_address in resolved {
+        match TcpStream::connect(socket_address) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = Some(err)
+        }
+    }
+
+    Err(match last_error {
+        None => ClientError::HostNotFound(address.to_string()),
+        Some(err) if err.kind() == io::ErrorKind::ConnectionRefused => ClientError::ConnectionRefused(address.to_string()),
+        Some(err) => ClientError::Connect(address.to_string(), err)
+    })
+}
+
+fn open_output(sample_rate: Option<u32>) -> Result<(OutputStream, OutputStreamHandle), ClientError> {
+    let Some(sample_rate) = sample_rate else {
+        return Ok(OutputStream::try_default()?);
+    };
+
+    let device = rodio::cpal::default_host()
+        .default_output_device()
+        .ok_or(ClientError::NoDevice)?;
+
+    let config = device
+        .supported_output_configs()?
+        .find(|config| {
+            config.min_sample_rate().0 <= sample_rate && sample_rate <= config.max_sample_rate().0
+        })
+        .ok_or(ClientError::UnsupportedSampleRate(sample_rate))?
+        .with_sample_rate(SampleRate(sample_rate));
+
+    Ok(OutputStream::try_from_device_config(&device, config)?)
+}
+
+fn init_terminal() -> Result<DefaultTerminal, ClientError> {
+    if !io::stdout().is_terminal() {
+        let err = io::Error::new(io::ErrorKind::Unsupported, "stdout is not a terminal");
+        return Err(ClientError::Terminal(err));
+    }
+
+    ratatui::try_init().map_err(|err| {
+        ratatui::restore();
+        ClientError::Terminal(err)
+    })
+}
+
+fn read_command(stream: &mut impl Read) -> Result<Command, ClientError> {
+    let mut bytes = [0_u8; 1];
+    stream.read_exact(&mut bytes)?;
+
+    let numeric = u8::from_be_bytes(bytes);
+
+    match numeric {
+        1 => Ok(Command::Play),
+        2 => Ok(Command::Transfer),
+        3 => Ok(Command::Pause),
+        4 => Ok(Command::Repeat),
+        6 => Ok(Command::Motd),
+        7 => Ok(Command::Reveal),
+        8 => Ok(Command::Chime),
+        9 => Ok(Command::PlayState),
+        10 => Ok(Command::Hint),
+        11 => Ok(Command::HostState),
+        12 => Ok(Command::Resync),
+        13 => Ok(Command::QuizName),
+        14 => Ok(Command::ShowAnswer),
+        15 => Ok(Command::Queued),
+        16 => Ok(Command::SeekForward),
+        17 => Ok(Command::SeekBack),
+        18 => Ok(Command::Summary),
+        19 => Ok(Command::Shutdown),
+        20 => Ok(Command::Countdown),
+        21 => Ok(Command::PreloadStart),
+        22 => Ok(Command::PreloadChunk),
+        23 => Ok(Command::SwapPreloaded),
+        24 => Ok(Command::KeepSong),
+        _ => Err(ClientError::UnknownCommand(numeric))
+    }
+}
+
+/// Payloads are framed with a 64-bit length. Read into memory they are limited to
+/// `MAX_IN_MEMORY_PAYLOAD`, songs written to --song-dir are not. The buffer grows with
+/// what actually arrives, so a lying length cannot allocate more than was sent.
+fn read_data(stream: &mut impl Read) -> Result<Vec<u8>, ClientError> {
+    let mut bytes_to_read = [0_u8; 64 / 8];
+    stream.read_exact(&mut bytes_to_read)?;
+    let bytes = u64::from_be_bytes(bytes_to_read);
+
+    if bytes > MAX_IN_MEMORY_PAYLOAD {
+        return Err(ClientError::TooLarge(bytes));
+    }
+    let mut data = Vec::new();
+    let received = stream.take(bytes).read_to_end(&mut data)? as u64;
+
+    if received != bytes {
+        return Err(ClientError::Truncated { received, expected: bytes });
+    }
+    Ok(data)
+}
+
+fn read_data_to_file(stream: &mut impl Read, path: &Path) -> Result<(), ClientError> {
+    let mut bytes_to_read = [0_u8; 64 / 8];
+    stream.read_exact(&mut bytes_to_read)?;
+    let bytes = u64::from_be_bytes(bytes_to_read);
+
+    let mut file = File::create(path)?;
+    let copied = io::copy(&mut stream.take(bytes), &mut file)?;
+
+    if copied != bytes {
+        return Err(ClientError::Truncated { received: copied, expected: bytes });
+    }
+    Ok(())
+}
+
+/// How long to wait before the given reconnect attempt, starting at 1.
+fn reconnect_delay(attempt: u32) -> Duration {
+    let doublings = attempt.saturating_sub(1).min(16);
+    (RECONNECT_DELAY * (1 << doublings)).min(MAX_RECONNECT_DELAY)
+}
+
+/// The host may stay quiet for as long as it likes between messages, but once one has
+/// started it has to arrive within `timeout`, a stall counts as a lost server.
+fn stream_handler(mut stream: TcpStream, sender: Sender<AppEvent>, song_dir: Option<PathBuf>, timeout: Option<Duration>) {
+    let mut num_song = 0;
+
+    loop {
+        if stream.set_read_timeout(None).is_err() || !matches!(stream.peek(&mut [0_u8; 1]), Ok(1..)) {
+            break;
+        }
+        if stream.set_read_timeout(timeout).is_err() {
+            break;
+        }
+        let events = match read_events(&mut stream, song_dir.as_deref(), &mut num_song) {
+            Ok(events) => events,
+            // The rest of the message was skipped, so the stream is still in step and only this message is lost.
+            Err(err @ (ClientError::Decode(_) | ClientError::Text(_) | ClientError::UnknownCommand(_) | ClientError::TooLarge(_))) => {
+                warn!("Skipped a message from the server: {}", err);
+                continue;
+            }
+            Err(err) => {
+                error!("Could not read from the server: {}", err);
+                break;
+            }
+        };
+        for event in events {
+            // Waiting here holds back later messages too, so a pause right after still lands after the start.
+            if let AppEvent::PlayAt(at) = event {
+                thread::sleep(start_delay(at, unix_millis()));
+            }
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+    }
+    let _ = sender.send(AppEvent::Disconnected);
+}
+
+/// FNV-1a over the song's bytes, the server hashes the same way.
+fn song_hash(mut data: impl Read) -> io::Result<u64> {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut buffer = [0_u8; 8192];
+    loop {
+        let read = data.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hash);
+        }
+        for &byte in &buffer[..read] {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// How long to wait for a scheduled start, capped in case this clock is far behind the host's.
+fn start_delay(at: u64, now: u64) -> Duration {
+    Duration::from_millis(at.saturating_sub(now)).min(MAX_START_DELAY)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Finds the next message and returns its length. Bytes before the magic are skipped,
+/// so a misread message costs only itself and not the connection.
+fn read_framed_message(stream: &mut impl Read) -> Result<u64, ClientError> {
+    let mut window = [0_u8; 4];
+    stream.read_exact(&mut window)?;
+    while window != FRAME_MAGIC {
+        let mut next = [0_u8; 1];
+        stream.read_exact(&mut next)?;
+        window.rotate_left(1);
+        window[3] = next[0];
+    }
+
+    let mut length = [0_u8; 8];
+    stream.read_exact(&mut length)?;
+    Ok(u64::from_be_bytes(length))
+}
+
+fn read_events(stream: &mut impl Read, song_dir: Option<&Path>, num_song: &mut u32) -> Result<Vec<AppEvent>, ClientError> {
+    let length = read_framed_message(stream)?;
+    let mut message = stream.take(length);
+    let events = read_message_events(&mut message, song_dir, num_song);
+
+    // Whatever was left unread still belongs to this message.
+    io::copy(&mut message, &mut io::sink())?;
+    events
+}
+
+fn read_message_events(stream: &mut impl Read, song_dir: Option<&Path>, num_song: &mut u32) -> Result<Vec<AppEvent>, ClientError> {
+    let command = read_command(stream)?;
+
+    let events = match command {
+        Command::Transfer => {
+            let format = read_format(stream)?;
+            let song = match song_dir {
+                None => Song::Memory(read_data(stream)?, format),
+                Some(dir) => {
+                    // Every song gets a fresh file, the previous one may still be open for playback.
+                    *num_song += 1;
+                    let name = match &format {
+                        Some(format) => format!("musicquiz-song-{}.{}", num_song, format),
+                        None => format!("musicquiz-song-{}", num_song)
+                    };
+                    let path = dir.join(name);
+                    read_data_to_file(stream, &path)?;
+                    Song::File(path)
+                }
+            };
+            vec![AppEvent::SongData(song)]
+        }
+        Command::Motd => vec![AppEvent::Motd(read_string(stream)?)],
+        Command::QuizName => vec![AppEvent::QuizName(read_string(stream)?)],
+        Command::ShowAnswer => vec![AppEvent::Answer(serde_json::from_str(&read_string(stream)?)?)],
+        Command::Hint => vec![AppEvent::Hint(read_string(stream)?)],
+        Command::HostState => vec![AppEvent::HostState(serde_json::from_str(&read_string(stream)?)?)],
+        Command::Resync => vec![AppEvent::Resync(serde_json::from_str(&read_string(stream)?)?)],
+        Command::Summary => vec![AppEvent::Summary(serde_json::from_str(&read_string(stream)?)?)],
+        Command::PreloadStart => {
+            let format = read_format(stream)?;
+            let mut size = [0_u8; 8];
+            stream.read_exact(&mut size)?;
+            vec![AppEvent::PreloadStart(format, u64::from_be_bytes(size))]
+        }
+        Command::PreloadChunk => {
+            // The chunk is the rest of the message.
+            let mut chunk = Vec::new();
+            stream.read_to_end(&mut chunk)?;
+            vec![AppEvent::PreloadChunk(chunk)]
+        }
+        Command::Countdown => {
+            let mut left = [0_u8; 8];
+            let mut total = [0_u8; 8];
+            stream.read_exact(&mut left)?;
+            stream.read_exact(&mut total)?;
+            vec![AppEvent::Countdown(u64::from_be_bytes(left), u64::from_be_bytes(total))]
+        }
+        Command::SeekForward | Command::SeekBack => {
+            let mut step = [0_u8; 8];
+            stream.read_exact(&mut step)?;
+            let step = Duration::from_secs(u64::from_be_bytes(step));
+            vec![AppEvent::Seek(matches!(command, Command::SeekForward), step)]
+        }
+        Command::Queued => {
+            let mut position = [0_u8; 8];
+            stream.read_exact(&mut position)?;
+            vec![AppEvent::Queued(u64::from_be_bytes(position))]
+        }
+        Command::Play => {
+            // Older servers send no start time, their songs start right away.
+            let mut at = [0_u8; 8];
+            if stream.read_exact(&mut at).is_err() {
+                return Ok(vec![AppEvent::Command(command)]);
+            }
+            let mut events = vec![AppEvent::PlayAt(u64::from_be_bytes(at))];
+
+            // Newer servers add the song number and the total.
+            let mut position = [0_u8; 16];
+            if stream.read_exact(&mut position).is_ok() {
+                let (song, total) = position.split_at(8);
+                let song = u64::from_be_bytes(song.try_into().unwrap()) as usize;
+                let total = u64::from_be_bytes(total.try_into().unwrap()) as usize;
+                events.insert(0, AppEvent::SongPosition(song, total));
+            }
+            events
+        }
+        Command::PlayState => {
+            let mut playing = [0_u8; 1];
+            stream.read_exact(&mut playing)?;
+            vec![AppEvent::PlayState(playing[0] != 0)]
+        }
+        Command::Reveal => match read_title_grading(stream) {
+            Ok(grading) => vec![AppEvent::TitleGrading(grading), AppEvent::Command(command)],
+            // A broken payload still reveals, the popup then shows a placeholder.
+            Err(_) => vec![AppEvent::Command(command)]
+        },
+        _ => vec![AppEvent::Command(command)]
+    };
+
+    Ok(events)
+}
+
+fn read_title_grading(stream: &mut impl Read) -> Result<TitleGrading, ClientError> {
+    Ok(serde_json::from_str(&read_string(stream)?)?)
+}
+
+fn read_string(stream: &mut impl Read) -> Result<String, ClientError> {
+    Ok(String::from_utf8(read_data(stream)?)?)
+}
+
+/// The song's file extension, anything that could not be a plain extension is dropped.
+fn read_format(stream: &mut impl Read) -> Result<Option<String>, ClientError> {
+    let format = read_string(stream)?.to_ascii_lowercase();
+    Ok(Some(format).filter(|format| !format.is_empty() && format.chars().all(|c| c.is_ascii_alphanumeric())))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Smallest popup that still fits a line of text inside its border.
+const MIN_POPUP_WIDTH: u16 = 30;
+const MIN_POPUP_HEIGHT: u16 = 5;
+
+fn popup_area(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+    // On a tiny terminal the whole screen is the best we can do.
+    if area.width <= MIN_POPUP_WIDTH || area.height <= MIN_POPUP_HEIGHT {
+        return area;
+    }
+
+    let percent = |length: u16, percent: u16| (length as u32 * percent as u32 / 100) as u16;
+    let width = percent(area.width, percent_x).max(MIN_POPUP_WIDTH);
+    let height = percent(area.height, percent_y).max(MIN_POPUP_HEIGHT);
+
+    let vertical = Layout::vertical([Constraint::Length(height)]).flex(Flex::Center);
+    let horizontal = Layout::horizontal([Constraint::Length(width)]).flex(Flex::Center);
+    let [area] = vertical.areas(area);
+    let [area] = horizontal.areas(area);
+    area
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct MockState {
+        loaded: usize,
+        playing: bool,
+        volume: f32,
+        tones: usize,
+        finished: bool,
+        position: Duration,
+        level: f32
+    }
+
+    struct MockOutput {
+        state: Rc<RefCell<MockState>>
+    }
+
+    impl AudioOutput for MockOutput {
+        fn load(&mut self, song: Song) -> Result<Option<Duration>, ClientError> {
+            if let Song::Memory(data, _) = song {
+                if data.is_empty() {
+                    return Err(ClientError::Song(DecoderError::UnrecognizedFormat));
+                }
+            }
+            let mut state = self.state.borrow_mut();
+            state.loaded += 1;
+            state.playing = false;
+            Ok(Some(Duration::from_secs(120)))
+        }
+
+        fn position(&self) -> Duration {
+            self.state.borrow().position
+        }
+
+        fn level(&self) -> f32 {
+            self.state.borrow().level
+        }
+
+        fn seek(&mut self, position: Duration) -> Result<(), SeekError> {
+            self.state.borrow_mut().position = position;
+            Ok(())
+        }
+
+        fn play(&mut self) {
+            self.state.borrow_mut().playing = true;
+        }
+
+        fn pause(&mut self) {
+            self.state.borrow_mut().playing = false;
+        }
+
+        fn set_volume(&mut self, volume: f32) {
+            self.state.borrow_mut().volume = volume;
+        }
+
+        fn is_finished(&self) -> bool {
+            self.state.borrow().finished
+        }
+
+        fn play_tone(&mut self, _tone: Tone, _volume: f32) {
+            self.state.borrow_mut().tones += 1;
+        }
+    }
+
+    fn test_app(state: AppState) -> (App, Rc<RefCell<MockState>>) {
+        let (tx, rx) = mpsc::channel::<AppEvent>();
+        let mock = Rc::new(RefCell::new(MockState::default()));
+
+        let app = App {
+            connection_string: String::new(),
+            nickname: String::new(),
+            motd: None,
+            quiz_name: None,
+            state,
+            event_loop: rx,
+            stream: None,
+            connection: None,
+            event_sender: tx,
+            current_song: None,
+            song_dir: None,
+            reconnect_attempts: 0,
+            read_timeout: None,
+            status_message: None,
+            input_warning: None,
+            current_title_grading: None,
+            current_answer: None,
+            reveal_at: None,
+            reveal_timeout: None,
+            reveal_generation: 0,
+            hints: Vec::new(),
+            grade_style: GradeStyle::Words,
+            detailed_reveal: false,
+            color: None,
+            mirror: false,
+            host_state: None,
+            song_position: None,
+            song_duration: None,
+            song_elapsed: Duration::ZERO,
+            last_heartbeat: Instant::now(),
+            audio: Box::new(MockOutput { state: mock.clone() }),
+            volume: 0.5,
+            min_volume: 0.05,
+            muted: None,
+            solo: None,
+            ambiance: false,
+            buzzed: false,
+            guess: None,
+            summary: None,
+            countdown: None,
+            next_song: None,
+            energy: 0.0,
+            loading: false,
+            exit: false,
+        };
+
+        (app, mock)
+    }
+
+    fn run(app: &mut App, events: Vec<AppEvent>) {
+        for event in events {
+            app.update(event);
+        }
+    }
+
+    fn key(code: KeyCode) -> AppEvent {
+        AppEvent::CrossTerm(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)))
+    }
+
+    /// Frames a payload the way the server's `write_string` does.
+    fn framed(payload: &str) -> Vec<u8> {
+        let mut bytes = (payload.len() as u64).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload.as_bytes());
+        bytes
+    }
+
+    fn message(code: u8, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = FRAME_MAGIC.to_vec();
+        bytes.extend_from_slice(&(1 + payload.len() as u64).to_be_bytes());
+        bytes.push(code);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn transfer_carries_the_file_format() {
+        let mut payload = framed("FLAC");
+        payload.extend(framed("abc"));
+        let mut bytes = message(2, &payload);
+        bytes.extend(message(2, &[framed("../x"), framed("abc")].concat()));
+        let mut stream = bytes.as_slice();
+
+        let events = read_events(&mut stream, None, &mut 0).unwrap();
+        assert!(matches!(events.as_slice(), [AppEvent::SongData(Song::Memory(data, Some(format)))] if data == b"abc" && format == "flac"));
+        let events = read_events(&mut stream, None, &mut 0).unwrap();
+        assert!(matches!(events.as_slice(), [AppEvent::SongData(Song::Memory(_, None))]));
+    }
+
+    #[test]
+    fn summary_shows_the_final_score() {
+        let bytes = message(18, &framed(r#"{"points":7,"possible":10,"songs":5}"#));
+        let mut stream = bytes.as_slice();
+        let events = read_events(&mut stream, None, &mut 0).unwrap();
+
+        let (mut app, _) = test_app(AppState::Paused);
+        run(&mut app, events);
+        assert!(matches!(app.summary, Some(Summary { points: 7, possible: 10, songs: 5 })));
+    }
+
+    #[test]
+    fn absurd_payload_sizes_are_refused() {
+        let mut payload = u64::MAX.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"abc");
+        assert!(matches!(read_data(&mut payload.as_slice()), Err(ClientError::TooLarge(u64::MAX))));
+
+        let mut payload = 1000_u64.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"abc");
+        assert!(matches!(read_data(&mut payload.as_slice()), Err(ClientError::Truncated { received: 3, expected: 1000 })));
+    }
+
+    #[test]
+    fn garbage_before_a_message_is_skipped() {
+        let mut bytes = vec![0xde, 0xad, b'M', b'U'];
+        bytes.extend(message(13, &framed("Pub Quiz")));
+        let mut stream = bytes.as_slice();
+
+        let events = read_events(&mut stream, None, &mut 0).unwrap();
+        assert!(matches!(events.as_slice(), [AppEvent::QuizName(name)] if name == "Pub Quiz"));
+    }
+
+    #[test]
+    fn unknown_message_is_skipped_without_losing_the_next() {
+        let mut bytes = message(99, b"whatever");
+        bytes.extend(message(9, &[1]));
+        let mut stream = bytes.as_slice();
+
+        assert!(matches!(read_events(&mut stream, None, &mut 0), Err(ClientError::UnknownCommand(99))));
+        let events = read_events(&mut stream, None, &mut 0).unwrap();
+        assert!(matches!(events.as_slice(), [AppEvent::PlayState(true)]));
+    }
+
+    #[test]
+    fn ungraded_reveal_stays_ungraded() {
+        let payload = r#"{"title":"Song","interpret":"Band","title_correct":null,"interpret_correct":true,"title_points":1,"interpret_points":2,"reveal_delay":0}"#;
+        let grading = read_title_grading(&mut framed(payload).as_slice()).unwrap();
+
+        assert_eq!(grading.title_correct, None);
+        assert_eq!(grading.interpret_correct, Some(true));
+        assert_eq!(grading.interpret_points, 2);
+    }
+
+    #[test]
+    fn grading_without_points_still_reads() {
+        let payload = r#"{"title":"Song","interpret":"Band","title_correct":false,"interpret_correct":null}"#;
+        let grading = read_title_grading(&mut framed(payload).as_slice()).unwrap();
+
+        assert_eq!(grading.title_correct, Some(false));
+        assert_eq!(grading.interpret_correct, None);
+        assert_eq!(grading.reveal_delay, 0);
+    }
+
+    fn grading() -> TitleGrading {
+        TitleGrading {
+            title: "Song".to_string(),
+            interpret: "Band".to_string(),
+            title_correct: Some(true),
+            interpret_correct: None,
+            title_points: 1,
+            interpret_points: 1,
+            reveal_delay: 0
+        }
+    }
+
+    #[test]
+    fn remembered_nickname_skips_the_prompt_only_when_asked() {
+        assert!(matches!(initial_state("dominik", true), AppState::Disconnected));
+        assert!(matches!(initial_state("dominik", false), AppState::EnterNickname));
+        assert!(matches!(initial_state("", true), AppState::EnterNickname));
+    }
+
+    #[test]
+    fn unreachable_server_from_the_command_line_falls_back_to_the_prompt() {
+        let (mut app, _) = test_app(AppState::Disconnected);
+        app.connect_on_start("127.0.0.1:1");
+
+        assert!(matches!(app.state, AppState::Disconnected));
+        assert!(app.status_message.is_some());
+    }
+
+    #[test]
+    fn connect_errors_tell_bad_addresses_from_refusals() {
+        assert!(matches!(connect_to("quiz"), Err(ClientError::InvalidAddress(_))));
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        assert!(matches!(connect_to(&format!(" 127.0.0.1:{} ", port)), Err(ClientError::ConnectionRefused(_))));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(connect_to(&listener.local_addr().unwrap().to_string()).is_ok());
+    }
+
+    #[test]
+    fn failed_connect_keeps_the_address_and_explains() {
+        let (mut app, _) = test_app(AppState::Disconnected);
+        app.connection_string = "quiz".to_string();
+        run(&mut app, vec![key(KeyCode::Enter)]);
+
+        assert!(matches!(app.state, AppState::Disconnected));
+        assert_eq!(app.connection_string, "quiz");
+        assert!(app.status_message.as_deref().is_some_and(|message| message.contains("not a server address")));
+    }
+
+    #[test]
+    fn entering_a_nickname_moves_to_server_entry() {
+        let (mut app, _) = test_app(AppState::EnterNickname);
+        run(&mut app, vec![key(KeyCode::Char('a')), key(KeyCode::Char('b')), key(KeyCode::Enter)]);
+
+        assert_eq!(app.nickname, "ab");
+        assert!(matches!(app.state, AppState::Disconnected));
+    }
+
+    #[test]
+    fn playback_progress_follows_the_song_while_playing() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::Command(Command::Play)]);
+        assert_eq!(app.song_duration, Some(Duration::from_secs(120)));
+
+        mock.borrow_mut().position = Duration::from_secs(30);
+        run(&mut app, vec![AppEvent::Tick]);
+        assert_eq!(app.song_elapsed, Duration::from_secs(30));
+
+        run(&mut app, vec![AppEvent::Command(Command::Pause)]);
+        mock.borrow_mut().position = Duration::from_secs(45);
+        run(&mut app, vec![AppEvent::Tick]);
+        assert_eq!(app.song_elapsed, Duration::from_secs(30));
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None))]);
+        assert_eq!(app.song_elapsed, Duration::ZERO);
+    }
+
+    #[test]
+    fn fade_out_ramps_down_and_ends_the_song() {
+        let trigger = Arc::new(AtomicBool::new(false));
+        let song = rodio::buffer::SamplesBuffer::new(1, 1000, vec![1000_i16; 100]);
+        let mut source = FadeOut::new(song, trigger.clone(), Duration::from_millis(4));
+        assert_eq!(source.next(), Some(1000));
+
+        trigger.store(true, Ordering::Relaxed);
+        assert_eq!(source.collect::<Vec<_>>(), vec![750, 500, 250, 0]);
+    }
+
+    #[test]
+    fn ambiance_follows_the_song_level_and_fades_when_paused() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        app.ambiance = true;
+        mock.borrow_mut().level = 0.8;
+
+        run(&mut app, vec![AppEvent::Tick]);
+        assert_eq!(app.energy, 0.0);
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::Command(Command::Play), AppEvent::Tick, AppEvent::Tick]);
+        let playing = app.energy;
+        assert!(playing > 0.0 && playing <= 0.8);
+
+        run(&mut app, vec![AppEvent::Command(Command::Pause), AppEvent::Tick]);
+        assert!(app.energy < playing);
+    }
+
+    #[test]
+    fn seeking_moves_within_the_loaded_song() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![AppEvent::Seek(true, Duration::from_secs(10))]);
+        assert_eq!(mock.borrow().position, Duration::ZERO);
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::Seek(true, Duration::from_secs(10))]);
+        assert_eq!(mock.borrow().position, Duration::from_secs(10));
+
+        run(&mut app, vec![AppEvent::Seek(false, Duration::from_secs(30))]);
+        assert_eq!(mock.borrow().position, Duration::ZERO);
+
+        mock.borrow_mut().position = Duration::from_secs(115);
+        run(&mut app, vec![AppEvent::Seek(true, Duration::from_secs(10))]);
+        assert_eq!(app.song_elapsed, Duration::from_secs(120));
+    }
+
+    #[test]
+    fn countdown_pauses_when_it_runs_out() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::Command(Command::Play)]);
+
+        let mut payload = 5_u64.to_be_bytes().to_vec();
+        payload.extend_from_slice(&30_u64.to_be_bytes());
+        let bytes = message(20, &payload);
+        run(&mut app, read_events(&mut bytes.as_slice(), None, &mut 0).unwrap());
+        assert_eq!(app.countdown, Some((5, 30)));
+        assert!(mock.borrow().playing);
+
+        run(&mut app, vec![AppEvent::Countdown(0, 30)]);
+        assert!(matches!(app.state, AppState::Paused));
+        assert!(!mock.borrow().playing);
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None))]);
+        assert!(app.countdown.is_none());
+    }
+
+    #[test]
+    fn preloaded_song_is_swapped_in() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        let mut bytes = message(21, &[framed("mp3"), 4_u64.to_be_bytes().to_vec()].concat());
+        bytes.extend(message(22, b"ab"));
+        bytes.extend(message(22, b"cd"));
+        bytes.extend(message(23, &[]));
+        let mut stream = bytes.as_slice();
+        for _ in 0..4 {
+            run(&mut app, read_events(&mut stream, None, &mut 0).unwrap());
+        }
+
+        assert!(app.next_song.is_none());
+        assert!(matches!(&app.current_song, Some(Song::Memory(data, Some(format))) if data == b"abcd" && format == "mp3"));
+        assert_eq!(mock.borrow().loaded, 1);
+    }
+
+    #[test]
+    fn incomplete_preload_is_not_played() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![AppEvent::PreloadStart(None, 10), AppEvent::PreloadChunk(vec![1, 2]), AppEvent::Command(Command::SwapPreloaded)]);
+
+        assert!(app.current_song.is_none());
+        assert_eq!(mock.borrow().loaded, 0);
+    }
+
+    #[test]
+    fn play_carries_a_start_time() {
+        let mut bytes = message(1, &1234_u64.to_be_bytes());
+        bytes.extend(message(1, &[]));
+        let mut stream = bytes.as_slice();
+        assert!(matches!(read_events(&mut stream, None, &mut 0).unwrap()[..], [AppEvent::PlayAt(1234)]));
+        assert!(matches!(read_events(&mut stream, None, &mut 0).unwrap()[..], [AppEvent::Command(Command::Play)]));
+
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::PlayAt(1234)]);
+        assert!(matches!(app.state, AppState::Playing));
+        assert!(mock.borrow().playing);
+    }
+
+    #[test]
+    fn play_tells_the_song_number() {
+        let payload = [1234_u64, 3, 20].map(u64::to_be_bytes).concat();
+        let (mut app, _) = test_app(AppState::Paused);
+        run(&mut app, read_events(&mut message(1, &payload).as_slice(), None, &mut 0).unwrap());
+
+        assert_eq!(app.song_position, Some((3, 20)));
+    }
+
+    #[test]
+    fn start_delay_is_capped() {
+        assert_eq!(start_delay(1300, 1000), Duration::from_millis(300));
+        assert_eq!(start_delay(1000, 1300), Duration::ZERO);
+        assert_eq!(start_delay(u64::MAX, 0), MAX_START_DELAY);
+    }
+
+    #[test]
+    fn kept_song_is_started_over() {
+        assert_eq!(song_hash(b"a".as_slice()).unwrap(), 0xaf63_dc4c_8601_ec8c);
+
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::Command(Command::KeepSong)]);
+        assert_eq!(mock.borrow().loaded, 2);
+        assert!(matches!(&app.current_song, Some(Song::Memory(data, None)) if data == &[1]));
+    }
+
+    #[test]
+    fn solo_mode_plays_local_songs_in_order() {
+        let dir = env::temp_dir().join(format!("musicquiz-solo-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["b.mp3", "a.ogg", "notes.txt"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let songs = solo_songs(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(songs, vec![dir.join("a.ogg"), dir.join("b.mp3")]);
+
+        let (mut app, mock) = test_app(AppState::EnterNickname);
+        app.solo = Some(Solo { songs, index: 0 });
+        app.load_solo_song(0);
+        assert!(matches!(app.state, AppState::Ready));
+
+        run(&mut app, vec![key(KeyCode::Char('p'))]);
+        assert!(mock.borrow().playing);
+        run(&mut app, vec![key(KeyCode::Char('n')), key(KeyCode::Char('d'))]);
+        assert!(matches!(app.state, AppState::Ready));
+        assert_eq!(app.song_position, Some((2, 2)));
+        run(&mut app, vec![key(KeyCode::Char('n'))]);
+        assert_eq!(app.song_position, Some((1, 2)));
+        assert_eq!(mock.borrow().loaded, 3);
+        assert!(app.current_song.is_none());
+    }
+
+    #[test]
+    fn buzzing_works_once_per_song() {
+        let (mut app, _) = test_app(AppState::Paused);
+        run(&mut app, vec![key(KeyCode::Char(' '))]);
+        assert!(!app.buzzed);
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), key(KeyCode::Char(' '))]);
+        assert!(app.buzzed);
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None))]);
+        assert!(!app.buzzed);
+    }
+
+    #[test]
+    fn guesses_are_typed_in_a_popup() {
+        let (mut app, _) = test_app(AppState::Paused);
+        run(&mut app, vec![key(KeyCode::Char('g'))]);
+        assert!(app.guess.is_none());
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), key(KeyCode::Char('g')), key(KeyCode::Char('q'))]);
+        assert_eq!(app.guess.as_deref(), Some("q"));
+        assert!(!app.exit);
+
+        run(&mut app, vec![key(KeyCode::Enter)]);
+        assert!(app.guess.is_none());
+
+        run(&mut app, vec![key(KeyCode::Char('g')), key(KeyCode::Char('x')), key(KeyCode::Esc), key(KeyCode::Char('+'))]);
+        assert!(app.guess.is_none());
+        assert!(!app.exit);
+    }
+
+    #[test]
+    fn song_data_outside_a_game_is_ignored() {
+        let (mut app, mock) = test_app(AppState::EnterNickname);
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::TitleGrading(grading())]);
+
+        assert!(matches!(app.state, AppState::EnterNickname));
+        assert_eq!(mock.borrow().loaded, 0);
+        assert!(app.current_song.is_none());
+        assert!(app.current_title_grading.is_none());
+    }
+
+    #[test]
+    fn song_data_loads_paused_and_play_pause_follow_the_host() {
+        let (mut app, mock) = test_app(AppState::Paused);
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1, 2, 3], None))]);
+        assert!(matches!(app.state, AppState::Ready));
+        assert_eq!(mock.borrow().loaded, 1);
+        assert!(app.current_song.is_some());
+
+        run(&mut app, vec![AppEvent::Command(Command::Play)]);
+        assert!(matches!(app.state, AppState::Playing));
+        assert!(mock.borrow().playing);
+
+        run(&mut app, vec![AppEvent::Command(Command::Pause)]);
+        assert!(matches!(app.state, AppState::Paused));
+        assert!(!mock.borrow().playing);
+    }
+
+    #[test]
+    fn song_data_clears_the_loading_indicator() {
+        let (mut app, _) = test_app(AppState::Paused);
+        app.loading = true;
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None))]);
+
+        assert!(!app.loading);
+        assert!(matches!(app.state, AppState::Ready));
+    }
+
+    #[test]
+    fn repeat_reloads_the_current_song() {
+        let (mut app, mock) = test_app(AppState::Paused);
+
+        run(&mut app, vec![AppEvent::Command(Command::Repeat)]);
+        assert_eq!(mock.borrow().loaded, 0);
+
+        run(&mut app, vec![
+            AppEvent::SongData(Song::Memory(vec![1], None)),
+            AppEvent::Command(Command::Play),
+            AppEvent::Command(Command::Repeat)
+        ]);
+        assert_eq!(mock.borrow().loaded, 2);
+        assert!(matches!(app.state, AppState::Ready));
+    }
+
+    #[test]
+    fn reveal_without_grading_shows_placeholder() {
+        let (mut app, _) = test_app(AppState::Paused);
+        run(&mut app, vec![AppEvent::Command(Command::Reveal)]);
+
+        assert!(matches!(app.state, AppState::Revealing));
+        assert!(app.current_title_grading.is_none());
+    }
+
+    #[test]
+    fn hints_accumulate_until_the_next_song() {
+        let (mut app, _) = test_app(AppState::Paused);
+        run(&mut app, vec![
+            AppEvent::Hint("Genre: Rock".to_string()),
+            AppEvent::Hint("Year: 1975".to_string())
+        ]);
+        assert_eq!(app.hints, vec!["Genre: Rock", "Year: 1975"]);
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None))]);
+        assert!(app.hints.is_empty());
+    }
+
+    #[test]
+    fn host_state_replaces_the_previous_snapshot() {
+        let (mut app, _) = test_app(AppState::Paused);
+        let state = |song| HostState {
+            song,
+            total: 3,
+            titles_correct: 0,
+            interprets_correct: 0,
+            disputed: 0,
+            points: 0,
+            elapsed_secs: 0,
+            time_left_secs: None,
+            answer: None,
+            players: vec!["ab".to_string()],
+            game_over: None
+        };
+
+        run(&mut app, vec![AppEvent::HostState(state(1)), AppEvent::HostState(state(2))]);
+        assert_eq!(app.host_state.map(|state| state.song), Some(2));
+    }
+
+    #[test]
+    fn resync_clears_a_stale_reveal_and_follows_the_host() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![
+            AppEvent::SongData(Song::Memory(vec![1], None)),
+            AppEvent::TitleGrading(grading()),
+            AppEvent::Command(Command::Reveal)
+        ]);
+        assert!(matches!(app.state, AppState::Revealing));
+
+        run(&mut app, vec![AppEvent::Resync(Resync { song: 2, total: 5, playing: true, transfered: true })]);
+        assert!(matches!(app.state, AppState::Playing));
+        assert!(mock.borrow().playing);
+        assert!(app.current_title_grading.is_none());
+        assert_eq!(app.song_position, Some((2, 5)));
+    }
+
+    #[test]
+    fn answer_is_shown_without_grading() {
+        let (mut app, _) = test_app(AppState::Paused);
+        run(&mut app, vec![AppEvent::Answer(Answer { title: "A".to_string(), interpret: "B".to_string() })]);
+
+        assert!(matches!(app.state, AppState::ShowingAnswer));
+        assert!(app.current_title_grading.is_none());
+        assert_eq!(app.current_answer.map(|answer| answer.title), Some("A".to_string()));
+    }
+
+    #[test]
+    fn delayed_reveal_holds_the_answer_until_the_tick_after_the_delay() {
+        let (mut app, _) = test_app(AppState::Paused);
+        run(&mut app, vec![
+            AppEvent::TitleGrading(TitleGrading { reveal_delay: 3, ..grading() }),
+            AppEvent::Command(Command::Reveal),
+            AppEvent::Tick
+        ]);
+        assert!(matches!(app.state, AppState::Revealing));
+        assert!(app.reveal_at.is_some());
+
+        app.reveal_at = Some(Instant::now());
+        run(&mut app, vec![AppEvent::Tick]);
+        assert!(app.reveal_at.is_none());
+    }
+
+    #[test]
+    fn reveal_is_dismissed_after_the_timeout_unless_a_new_grading_arrived() {
+        let (mut app, _) = test_app(AppState::Paused);
+        app.reveal_timeout = Some(Duration::from_secs(60));
+        run(&mut app, vec![AppEvent::TitleGrading(grading()), AppEvent::Command(Command::Reveal)]);
+        let generation = app.reveal_generation;
+
+        run(&mut app, vec![AppEvent::TitleGrading(grading()), AppEvent::DismissReveal(generation)]);
+        assert!(matches!(app.state, AppState::Revealing));
+
+        let generation = app.reveal_generation;
+        run(&mut app, vec![AppEvent::DismissReveal(generation)]);
+        assert!(matches!(app.state, AppState::Paused));
+    }
+
+    #[test]
+    fn input_failures_warn_until_recovered() {
+        let (mut app, _) = test_app(AppState::Playing);
+        run(&mut app, vec![AppEvent::InputError("broken pipe".to_string())]);
+        assert!(app.input_warning.as_deref().is_some_and(|warning| warning.contains("broken pipe")));
+
+        run(&mut app, vec![AppEvent::InputRecovered]);
+        assert!(app.input_warning.is_none());
+
+        run(&mut app, vec![AppEvent::InputLost]);
+        assert!(app.input_warning.is_some());
+        assert!(!app.exit);
+        assert!(matches!(app.state, AppState::Playing));
+    }
+
+    #[test]
+    fn queue_position_updates_until_admitted() {
+        let (mut app, _) = test_app(AppState::Paused);
+        run(&mut app, vec![AppEvent::Queued(3), AppEvent::Queued(1)]);
+        assert!(matches!(app.state, AppState::Queued(1)));
+
+        run(&mut app, vec![AppEvent::Queued(0)]);
+        assert!(matches!(app.state, AppState::Paused));
+    }
+
+    #[test]
+    fn song_data_while_revealing_clears_the_reveal() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![
+            AppEvent::TitleGrading(grading()),
+            AppEvent::Command(Command::Reveal),
+        ]);
+        assert!(matches!(app.state, AppState::Revealing));
+        assert!(app.current_title_grading.is_some());
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None))]);
+        assert!(matches!(app.state, AppState::Ready));
+        assert!(app.current_title_grading.is_none());
+        assert_eq!(mock.borrow().loaded, 1);
+    }
+
+    #[test]
+    fn disconnect_while_playing_stops_audio() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![
+            AppEvent::SongData(Song::Memory(vec![1], None)),
+            AppEvent::Command(Command::Play),
+            AppEvent::Disconnected
+        ]);
+
+        assert!(matches!(app.state, AppState::Disconnected));
+        assert!(!mock.borrow().playing);
+    }
+
+    #[test]
+    fn tick_detects_the_end_of_playback() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::Command(Command::Play), AppEvent::Tick]);
+        assert!(matches!(app.state, AppState::Playing));
+
+        mock.borrow_mut().finished = true;
+        run(&mut app, vec![AppEvent::Tick]);
+        assert!(matches!(app.state, AppState::PlaybackFinished));
+    }
+
+    #[test]
+    fn chime_plays_without_touching_the_song() {
+        let (mut app, mock) = test_app(AppState::Playing);
+        run(&mut app, vec![AppEvent::Command(Command::Chime)]);
+
+        assert!(matches!(app.state, AppState::Playing));
+        assert_eq!(mock.borrow().tones, 2);
+    }
+
+    #[test]
+    fn play_state_sync_corrects_a_missed_command() {
+        let (mut app, mock) = test_app(AppState::Paused);
+
+        run(&mut app, vec![AppEvent::PlayState(true)]);
+        assert!(matches!(app.state, AppState::Paused));
+
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::PlayState(true)]);
+        assert!(matches!(app.state, AppState::Playing));
+        assert!(mock.borrow().playing);
+
+        run(&mut app, vec![AppEvent::PlayState(false)]);
+        assert!(matches!(app.state, AppState::Paused));
+        assert!(!mock.borrow().playing);
+    }
+
+    #[test]
+    fn leaving_returns_to_the_address_prompt() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        app.reconnect_attempts = 2;
+        app.nickname = "Dom".to_string();
+        app.connection_string = "quiz:6969".to_string();
+        run(&mut app, vec![AppEvent::QuizName("Pub Quiz".to_string()), AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::Command(Command::Play)]);
+
+        run(&mut app, vec![key(KeyCode::Char('d')), AppEvent::Disconnected]);
+        assert!(matches!(app.state, AppState::Disconnected));
+        assert!(!mock.borrow().playing);
+        assert!(app.current_song.is_none());
+        assert!(app.quiz_name.is_none());
+        assert!(app.connection_string.is_empty());
+        assert_eq!(app.nickname, "Dom");
+    }
+
+    #[test]
+    fn shutdown_finishes_the_quiz_without_reconnecting() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        app.reconnect_attempts = 2;
+        run(&mut app, vec![AppEvent::SongData(Song::Memory(vec![1], None)), AppEvent::Command(Command::Play)]);
+
+        let bytes = message(19, &[]);
+        let events = read_events(&mut bytes.as_slice(), None, &mut 0).unwrap();
+        run(&mut app, events);
+        assert!(matches!(app.state, AppState::Finished));
+        assert!(!mock.borrow().playing);
+
+        run(&mut app, vec![AppEvent::Disconnected]);
+        assert!(matches!(app.state, AppState::Finished));
+    }
+
+    #[test]
+    fn reconnect_gives_up_after_the_limit() {
+        let (mut app, _) = test_app(AppState::Playing);
+        app.reconnect_attempts = 2;
+
+        run(&mut app, vec![AppEvent::Disconnected]);
+        assert!(matches!(app.state, AppState::Reconnecting(1)));
+
+        run(&mut app, vec![AppEvent::Reconnect]);
+        assert!(matches!(app.state, AppState::Reconnecting(2)));
+
+        run(&mut app, vec![AppEvent::Reconnect]);
+        assert!(matches!(app.state, AppState::Disconnected));
+        assert!(app.status_message.is_some());
+    }
+
+    #[test]
+    fn volume_is_saved_and_loaded_again() {
+        let path = env::temp_dir().join(format!("musicquiz-settings-{}", process::id())).join("client.json");
+        assert_eq!(load_volume(&path), DEFAULT_VOLUME);
+
+        save_volume(&path, 0.8).unwrap();
+        assert_eq!(load_volume(&path), 0.8);
+
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(load_volume(&path), DEFAULT_VOLUME);
+        fs::write(&path, r#"{"volume":7.0}"#).unwrap();
+        assert_eq!(load_volume(&path), DEFAULT_VOLUME);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn reconnect_backs_off_up_to_a_cap() {
+        assert_eq!(reconnect_delay(1), RECONNECT_DELAY);
+        assert_eq!(reconnect_delay(2), RECONNECT_DELAY * 2);
+        assert_eq!(reconnect_delay(3), RECONNECT_DELAY * 4);
+        assert_eq!(reconnect_delay(10), MAX_RECONNECT_DELAY);
+        assert_eq!(reconnect_delay(u32::MAX), MAX_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn escape_stops_reconnecting() {
+        let (mut app, _) = test_app(AppState::Playing);
+        app.reconnect_attempts = 5;
+
+        run(&mut app, vec![AppEvent::Disconnected, key(KeyCode::Esc)]);
+        assert!(matches!(app.state, AppState::Disconnected));
+
+        run(&mut app, vec![AppEvent::Reconnect]);
+        assert!(matches!(app.state, AppState::Disconnected));
+        assert!(!app.exit);
+    }
+
+    #[test]
+    fn popup_area_stays_readable_on_small_terminals() {
+        let popup = popup_area(Rect::new(0, 0, 100, 50), 60, 20);
+        assert_eq!((popup.width, popup.height), (60, 10));
+
+        let popup = popup_area(Rect::new(0, 0, 40, 12), 60, 20);
+        assert_eq!((popup.width, popup.height), (MIN_POPUP_WIDTH, MIN_POPUP_HEIGHT));
+
+        let tiny = Rect::new(0, 0, 20, 4);
+        assert_eq!(popup_area(tiny, 60, 20), tiny);
+    }
+
+    #[test]
+    fn volume_keys_clamp_and_reach_the_output() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, (0..20).map(|_| key(KeyCode::Char('+'))).collect());
+        assert_eq!(app.volume, 1.0);
+        assert_eq!(mock.borrow().volume, 1.0);
+
+        run(&mut app, vec![key(KeyCode::Char('b'))]);
+        assert_eq!(mock.borrow().tones, 1);
+
+        run(&mut app, (0..40).map(|_| key(KeyCode::Char('-'))).collect());
+        assert_eq!(app.volume, 0.05);
+        assert_eq!(mock.borrow().volume, 0.05);
+    }
+
+    #[test]
+    fn mute_restores_the_previous_volume() {
+        let (mut app, mock) = test_app(AppState::Paused);
+        run(&mut app, vec![key(KeyCode::Char(']')), key(KeyCode::Char(']')), key(KeyCode::Char('m'))]);
+        assert_eq!(app.volume, 0.0);
+        assert_eq!(mock.borrow().volume, 0.0);
+
+        run(&mut app, vec![key(KeyCode::Char('m'))]);
+        assert!(app.muted.is_none());
+        assert!((app.volume - 0.52).abs() < 1e-6);
+        assert_eq!(mock.borrow().volume, app.volume);
+
+        // Stepping while muted unmutes and steps from the volume before.
+        run(&mut app, vec![key(KeyCode::Char('m')), key(KeyCode::Char('['))]);
+        assert!(app.muted.is_none());
+        assert!((app.volume - 0.51).abs() < 1e-6);
+    }
 }
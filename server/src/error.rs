@@ -0,0 +1,23 @@
+use std::io;
+use std::sync::mpsc::RecvError;
+
+use thiserror::Error;
+
+/// Everything that can go wrong while hosting a quiz.
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("invalid JSON: {0}")]
+    Decode(#[from] serde_json::Error),
+
+    #[error("invalid titles file: {0}")]
+    Titles(String),
+
+    #[error("must be run in an interactive terminal: {0}")]
+    Terminal(#[source] io::Error),
+
+    #[error("the event channel was closed")]
+    ChannelClosed(#[from] RecvError),
+}
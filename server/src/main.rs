@@ -1,41 +1,497 @@
-use std::error::Error;
-use std::fs::{self, File};
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+mod error;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Receiver;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
-use std::{thread, usize};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{process, thread};
+
+use clap::{Parser, ValueEnum};
+use log::{error, info, warn, LevelFilter};
+use rand::seq::SliceRandom;
+use ratatui::widgets::{List, ListItem, ListState};
+use serde::{Deserialize, Serialize};
 
-use ratatui::widgets::List;
-use serde::Deserialize;
+use error::ServerError;
 
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
-use ratatui::layout::{Constraint, Layout};
+use ratatui::layout::{Constraint, Flex, Layout};
 use ratatui::{
     buffer::Buffer,
     layout::Rect,
-    style::Stylize,
-    text::Line,
-    widgets::{Block, Paragraph, Widget},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Clear, Paragraph, Widget},
     DefaultTerminal, Frame,
 };
 
+#[derive(Parser, Debug)]
+#[command(about = "Host a music quiz")]
+struct Args {
+    /// The quiz's title list
+    #[arg(long)]
+    titles: PathBuf,
+
+    /// Address and port to accept clients on [default: 0.0.0.0:6969]
+    #[arg(long)]
+    bind: Option<String>,
+
+    /// Serve the game state as JSON over HTTP on this port, on the same address as --bind
+    #[arg(long)]
+    status_port: Option<u16>,
+
+    /// Points awarded for a correctly guessed title [default: 1]
+    #[arg(long)]
+    title_points: Option<u32>,
+
+    /// Points awarded for a correctly guessed interpret [default: 1]
+    #[arg(long)]
+    interpret_points: Option<u32>,
+
+    /// Welcome message shown to clients when they join
+    #[arg(long)]
+    motd: Option<String>,
+
+    /// Name of the quiz shown to clients, overrides the name in the title list
+    #[arg(long)]
+    quiz_name: Option<String>,
+
+    /// End the quiz automatically after this many minutes
+    #[arg(long)]
+    max_duration: Option<u64>,
+
+    /// Only count game time while a song is playing
+    #[arg(long)]
+    timer_follows_playback: bool,
+
+    /// Accept at most this many clients at once
+    #[arg(long)]
+    max_clients: Option<usize>,
+
+    /// Hold clients in a queue while the quiz is full instead of turning them away
+    #[arg(long)]
+    queue_when_full: bool,
+
+    /// Disconnect clients that stay silent for this many seconds
+    #[arg(long)]
+    idle_timeout: Option<u64>,
+
+    /// Seconds a client may stall a handshake or a transfer, 0 waits forever [default: 30]
+    #[arg(long)]
+    read_timeout: Option<u64>,
+
+    /// Advance to the next song this many seconds after a reveal
+    #[arg(long)]
+    auto_advance: Option<u64>,
+
+    /// Seconds players get once the host starts a countdown [default: 30]
+    #[arg(long)]
+    countdown: Option<u64>,
+
+    /// How often each song may be repeated
+    #[arg(long)]
+    max_replays: Option<u32>,
+
+    /// How gradings are displayed [default: words]
+    #[arg(long, value_enum)]
+    grade_style: Option<GradeStyle>,
+
+    /// Run without song files, only grading, reveals and scores are managed
+    #[arg(long)]
+    no_audio: bool,
+
+    /// Send the next song to clients while the current one plays, doubles the bandwidth used
+    #[arg(long)]
+    preload: bool,
+
+    /// How many clients a song is sent to at the same time [default: 4]
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    transfer_threads: Option<u16>,
+
+    /// Directory holding the numbered song files (1.mp3, 2.flac, ...) [default: .]
+    #[arg(long)]
+    music_dir: Option<PathBuf>,
+
+    /// File extensions tried in order when looking for a song [default: mp3,flac,ogg,wav]
+    #[arg(long, value_delimiter = ',')]
+    extensions: Option<Vec<String>>,
+
+    /// Write the played songs as an M3U playlist to this file when the game ends
+    #[arg(long)]
+    export_m3u: Option<PathBuf>,
+
+    /// Append every recorded grading as a JSON line to this file, for settling disputes
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+
+    /// Write the full results as JSON to this file when the server quits
+    #[arg(long)]
+    results: Option<PathBuf>,
+
+    /// Write titles and interprets corrected during the quiz back to the titles file
+    #[arg(long)]
+    save_edits: bool,
+
+    /// Program grading player guesses, called with the guess, the title and the interpret
+    #[arg(long)]
+    grader: Option<String>,
+
+    /// Load host settings from a profile file, flags given on the command line take precedence
+    #[arg(long)]
+    profile: Option<PathBuf>,
+
+    /// Save the effective host settings as a profile file
+    #[arg(long)]
+    save_profile: Option<PathBuf>,
+}
+
+/// Host settings that can be kept in a named profile file.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+struct Profile {
+    title_points: Option<u32>,
+    interpret_points: Option<u32>,
+    motd: Option<String>,
+    quiz_name: Option<String>,
+    max_duration: Option<u64>,
+    auto_advance: Option<u64>,
+    countdown: Option<u64>,
+    max_replays: Option<u32>,
+    max_clients: Option<usize>,
+    queue_when_full: bool,
+    idle_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    timer_follows_playback: bool,
+    grade_style: Option<GradeStyle>,
+    no_audio: bool,
+    preload: bool,
+    music_dir: Option<PathBuf>,
+    extensions: Option<Vec<String>>,
+    transfer_threads: Option<u16>,
+    bind: Option<String>,
+    status_port: Option<u16>,
+    grader: Option<String>,
+}
+
+impl Profile {
+    fn resolve(args: &Args) -> Result<Profile, ServerError> {
+        let profile = match &args.profile {
+            Some(path) => serde_json::from_str(&fs::read_to_string(path)?)?,
+            None => Profile::default(),
+        };
+
+        let profile = Profile {
+            title_points: args.title_points.or(profile.title_points),
+            interpret_points: args.interpret_points.or(profile.interpret_points),
+            motd: args.motd.clone().or(profile.motd),
+            quiz_name: args.quiz_name.clone().or(profile.quiz_name),
+            max_duration: args.max_duration.or(profile.max_duration),
+            auto_advance: args.auto_advance.or(profile.auto_advance),
+            countdown: args.countdown.or(profile.countdown),
+            max_replays: args.max_replays.or(profile.max_replays),
+            max_clients: args.max_clients.or(profile.max_clients),
+            queue_when_full: args.queue_when_full || profile.queue_when_full,
+            idle_timeout: args.idle_timeout.or(profile.idle_timeout),
+            read_timeout: args.read_timeout.or(profile.read_timeout),
+            timer_follows_playback: args.timer_follows_playback || profile.timer_follows_playback,
+            grade_style: args.grade_style.or(profile.grade_style),
+            no_audio: args.no_audio || profile.no_audio,
+            preload: args.preload || profile.preload,
+            music_dir: args.music_dir.clone().or(profile.music_dir),
+            extensions: args.extensions.clone().or(profile.extensions),
+            transfer_threads: args.transfer_threads.or(profile.transfer_threads),
+            bind: args.bind.clone().or(profile.bind),
+            status_port: args.status_port.or(profile.status_port),
+            grader: args.grader.clone().or(profile.grader),
+        };
+
+        if let Some(path) = &args.save_profile {
+            fs::write(path, serde_json::to_string_pretty(&profile)?)?;
+        }
+
+        Ok(profile)
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum GradeStyle {
+    /// "correct" / "incorrect"
+    #[default]
+    Words,
+    /// A filled or empty star
+    Stars,
+    /// The points the answer earned
+    Points,
+}
+
+impl GradeStyle {
+    fn span(&self, grade: Option<bool>, points: u32) -> Span<'static> {
+        match (self, grade) {
+            (_, None) => "not yet graded".gray().bold(),
+            (GradeStyle::Words, Some(true)) => "correct".green().bold(),
+            (GradeStyle::Words, Some(false)) => "incorrect".red().bold(),
+            (GradeStyle::Stars, Some(true)) => "★".green().bold(),
+            (GradeStyle::Stars, Some(false)) => "☆".red().bold(),
+            (GradeStyle::Points, Some(true)) => format!("+{}", points).green().bold(),
+            (GradeStyle::Points, Some(false)) => "+0".red().bold(),
+        }
+    }
+}
+
+/// The part of the current song the host is correcting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EditField {
+    Title,
+    Interpret,
+}
+
+/// Who the grading keys apply to, switched by the host between rounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum GradingMode {
+    /// One grading for the whole room
+    #[default]
+    Room,
+    /// The selected player is graded on their own
+    Player,
+}
+
+/// Game time that only advances while running, so it can sit out pauses.
+#[derive(Debug, Default)]
+struct GameClock {
+    accumulated: Duration,
+    running_since: Option<Instant>,
+}
+
+impl GameClock {
+    fn elapsed(&self) -> Duration {
+        self.accumulated + self.running_since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+    fn set_running(&mut self, running: bool) {
+        match (running, self.running_since) {
+            (true, None) => self.running_since = Some(Instant::now()),
+            (false, Some(since)) => {
+                self.accumulated += since.elapsed();
+                self.running_since = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Scoring {
+    title_points: u32,
+    interpret_points: u32,
+}
+
+impl Scoring {
+    fn points(&self, titles_correct: usize, interprets_correct: usize) -> u32 {
+        titles_correct as u32 * self.title_points
+            + interprets_correct as u32 * self.interpret_points
+    }
+}
+
+#[derive(Debug)]
 enum Command {
     Transfer,
     Play,
     Pause,
     Repeat,
+    Motd,
+    Reveal,
+    Chime,
+    PlayState,
+    Hint,
+    HostState,
+    Resync,
+    QuizName,
+    ShowAnswer,
+    Queued,
+    SeekForward,
+    SeekBack,
+    Summary,
+    Shutdown,
+    Countdown,
+    PreloadStart,
+    PreloadChunk,
+    SwapPreloaded,
+    KeepSong,
+}
+
+/// Messages a client sends to the server after the handshake.
+enum Request {
+    Resend,
+    Leave,
+    Heartbeat,
+    Buzz,
+    /// Followed by the guess as a length prefixed string
+    Guess,
+}
+
+impl Request {
+    fn from_code(code: u8) -> Option<Request> {
+        match code {
+            5 => Some(Request::Resend),
+            6 => Some(Request::Leave),
+            7 => Some(Request::Heartbeat),
+            8 => Some(Request::Buzz),
+            9 => Some(Request::Guess),
+            _ => None,
+        }
+    }
+}
+
+impl Command {
+    fn code(&self) -> u8 {
+        match self {
+            Command::Play => 1,
+            Command::Transfer => 2,
+            Command::Pause => 3,
+            Command::Repeat => 4,
+            Command::Motd => 6,
+            Command::Reveal => 7,
+            Command::Chime => 8,
+            Command::PlayState => 9,
+            Command::Hint => 10,
+            Command::HostState => 11,
+            Command::Resync => 12,
+            Command::QuizName => 13,
+            Command::ShowAnswer => 14,
+            Command::Queued => 15,
+            Command::SeekForward => 16,
+            Command::SeekBack => 17,
+            Command::Summary => 18,
+            Command::Shutdown => 19,
+            Command::Countdown => 20,
+            Command::PreloadStart => 21,
+            Command::PreloadChunk => 22,
+            Command::SwapPreloaded => 23,
+            Command::KeepSong => 24,
+        }
+    }
 }
 
+/// Number of consecutive terminal read failures after which the input
+/// thread gives up.
+const MAX_INPUT_FAILURES: u32 = 10;
+
+/// Song file extensions looked for unless configured otherwise, all of them decode on the client.
+const DEFAULT_EXTENSIONS: [&str; 4] = ["mp3", "flac", "ogg", "wav"];
+
+/// Where clients connect to unless told otherwise.
+const DEFAULT_BIND: &str = "0.0.0.0:6969";
+
+/// Where songs are looked for when no music directory is configured.
+const DEFAULT_MUSIC_DIR: &str = ".";
+
+/// Parallel song transfers unless configured otherwise.
+const DEFAULT_TRANSFER_THREADS: u16 = 4;
+
+/// Seconds a stalled socket is waited for unless configured otherwise.
+const DEFAULT_READ_TIMEOUT: u64 = 30;
+
+/// Starts every message to a client, so a reader that lost its place can find the next one.
+const FRAME_MAGIC: [u8; 4] = *b"MUSQ";
+
+/// Songs are written to clients in pieces of this size, reporting progress after each.
+const TRANSFER_CHUNK: usize = 64 * 1024;
+
+/// Longest handshake in bytes, checked before anything is allocated for it.
+const MAX_HANDSHAKE_LENGTH: u64 = 1024;
+
+/// Longest nickname in bytes a client may pick.
+const MAX_NICKNAME_LENGTH: usize = 64;
+
+/// Longest guess in bytes a client may send, anything longer is treated as a broken client.
+const MAX_GUESS_LENGTH: u64 = 256;
+
+/// Length of a countdown unless configured otherwise.
+const DEFAULT_COUNTDOWN: u64 = 30;
+
+/// How far ahead a play command schedules the start, so every client gets it in time.
+const PLAY_LEAD: Duration = Duration::from_millis(300);
+
+/// Rounds one press of page up or down moves through the grading history.
+const HISTORY_PAGE: usize = 10;
+
+/// How far one press of the arrow keys seeks in the current song.
+const SEEK_STEP: Duration = Duration::from_secs(10);
+
+/// How long before the end of a capped quiz the host gets warned.
+const DURATION_WARNING: Duration = Duration::from_secs(60);
+
+/// Read timeouts in a row after which a client counts as stalled, its heartbeats stopped.
+const MAX_STALLED_READS: u32 = 2;
+
+/// Longest a grader may take for one guess before it is killed.
+const GRADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest wait for a running transfer on quit, so clients still hear about the shutdown.
+const SHUTDOWN_WAIT: Duration = Duration::from_secs(10);
+
+/// How long a notice like an ignored key stays on screen.
+const NOTICE_DURATION: Duration = Duration::from_secs(3);
+
 enum AppEvent {
     CrossTerm(crossterm::event::Event),
     ClientUpdate,
+    ClientRequest(usize, Request),
+    ClientLeft(usize),
+    /// A player buzzed in, stamped when the reader saw it
+    Buzz {
+        id: usize,
+        nickname: String,
+        at: Instant,
+    },
+    Guess {
+        id: usize,
+        nickname: String,
+        guess: String,
+    },
+    /// A second of the countdown started as `generation` passed
+    Countdown {
+        generation: u64,
+        left: u64,
+    },
+    /// The grader's verdict on a guess made during the song of `generation`, none if it failed
+    AutoGraded {
+        nickname: String,
+        generation: u64,
+        grading: Option<Grading>,
+    },
+    InputError(String),
+    InputRecovered,
+    InputLost,
+    Tick,
+    /// Bytes written so far and in total over all clients
+    TransferProgress(u64, u64),
+    /// Ids of the clients the transfer failed for
+    TransferFinished(Vec<usize>),
+    /// A resend to one client is done, false if writing to it failed
+    ResendFinished(usize, bool),
+    /// Clients hold this title now, sending it again can be skipped
+    PreloadFinished(usize),
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 struct TitleInfo {
     title: String,
     interpret: String,
+    /// Hints handed out one at a time, easiest to guess from last
+    #[serde(default)]
+    clues: Vec<String>,
+    /// Seconds clients wait after a reveal before showing the answer
+    #[serde(default)]
+    reveal_delay: u64,
+    /// Played so everyone can check their audio, never graded or scored
+    #[serde(default)]
+    warmup: bool,
 }
 
 impl Widget for TitleInfo {
@@ -53,56 +509,123 @@ impl Widget for TitleInfo {
     }
 }
 
-fn title_block(title: &str) -> Block {
+fn title_block(title: &str) -> Block<'_> {
     Block::bordered()
         .gray()
         .title(title.bold().into_centered_line())
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 struct TitleList {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
     titles: Vec<TitleInfo>,
 }
 
 struct ConnectionInfo {
-    active_clients: u8,
+    active_clients: usize,
+    waiting_clients: usize,
     transfered: bool,
+    transfer: Option<(u64, u64)>,
     playing: bool,
+    audio: bool,
 }
 
 impl Widget for ConnectionInfo {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        Paragraph::new(vec![
-            Line::from(vec![
-                "Number of clients: ".into(),
-                self.active_clients.to_string().yellow().bold(),
-            ]),
-            Line::from(vec![
+        let mut lines = vec![Line::from(vec![
+            "Number of clients: ".into(),
+            self.active_clients.to_string().yellow().bold(),
+        ])];
+
+        if self.waiting_clients > 0 {
+            lines.push(Line::from(vec![
+                "Waiting in queue: ".into(),
+                self.waiting_clients.to_string().yellow().bold(),
+            ]));
+        }
+
+        if let (true, Some((sent, total))) = (self.audio, self.transfer) {
+            let percent = (sent * 100).checked_div(total).unwrap_or(100);
+            lines.push(Line::from(vec![
+                "Transferring: ".into(),
+                format!("{}%", percent).cyan().bold(),
+                format!(" ({:.1} / {:.1} MB)", megabytes(sent), megabytes(total)).into(),
+            ]));
+        } else if self.audio {
+            lines.push(Line::from(vec![
                 "Transferred: ".into(),
                 self.transfered.to_string().yellow().bold(),
-            ]),
-            Line::from(vec![
+            ]));
+        }
+
+        if self.audio {
+            lines.push(Line::from(vec![
                 "Playing: ".into(),
                 self.playing.to_string().yellow().bold(),
-            ]),
-        ])
+            ]));
+        } else {
+            lines.push(Line::from(vec![
+                "Audio: ".into(),
+                "off, play the songs yourself".yellow().bold(),
+            ]));
+        }
+
+        Paragraph::new(lines)
         .block(title_block("Connection Info"))
         .gray()
         .render(area, buf);
     }
 }
 
+struct BuzzOrder {
+    /// Nicknames in buzz order, with the time behind the first buzz
+    buzzes: Vec<(String, Duration)>,
+}
+
+impl Widget for BuzzOrder {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lines: Vec<Line> = self
+            .buzzes
+            .into_iter()
+            .enumerate()
+            .map(|(index, (nickname, behind))| {
+                Line::from(vec![
+                    format!("{}. ", index + 1).yellow().bold(),
+                    nickname.into(),
+                    match index {
+                        0 => "".into(),
+                        _ => format!(" +{:.2}s", behind.as_secs_f64()).dark_gray(),
+                    },
+                ])
+            })
+            .collect();
+
+        Paragraph::new(lines)
+            .block(title_block("Buzzes"))
+            .gray()
+            .render(area, buf);
+    }
+}
+
 struct GameInfo {
-    titles_correct: u8,
-    interprets_correct: u8,
-    current_index: u8,
-    total_num: u8,
+    titles_correct: usize,
+    interprets_correct: usize,
+    current_index: usize,
+    total_num: usize,
+    disputed: usize,
+    scoring: Scoring,
+    elapsed: Duration,
+    time_left: Option<Duration>,
+    advance_in: Option<Duration>,
+    countdown: Option<u64>,
+    replays_left: Option<u32>,
 }
 
 impl Widget for GameInfo {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let incorrect_titles: u8 = self.current_index - self.titles_correct;
-        let incorrect_interprets: u8 = self.current_index - self.interprets_correct;
+        let incorrect_titles = self.current_index - self.disputed - self.titles_correct;
+        let incorrect_interprets = self.current_index - self.disputed - self.interprets_correct;
 
         Paragraph::new(vec![
             Line::from(vec![
@@ -121,6 +644,45 @@ impl Widget for GameInfo {
                 " / ".into(),
                 self.total_num.to_string().into(),
             ]),
+            Line::from(vec![
+                "Disputed: ".into(),
+                self.disputed.to_string().magenta().bold(),
+            ]),
+            Line::from(vec![
+                "Points: ".into(),
+                self.scoring
+                    .points(self.titles_correct, self.interprets_correct)
+                    .to_string()
+                    .yellow()
+                    .bold(),
+            ]),
+            Line::from(vec![
+                "Elapsed: ".into(),
+                format_duration(self.elapsed).into(),
+                match self.time_left {
+                    None => "".into(),
+                    Some(left) if left <= DURATION_WARNING => {
+                        format!(" ({} left)", format_duration(left)).red().bold()
+                    }
+                    Some(left) => format!(" ({} left)", format_duration(left)).into(),
+                },
+            ]),
+            Line::from(match self.advance_in {
+                None => vec![],
+                Some(left) => vec![
+                    "Next song in: ".into(),
+                    format_duration(left).cyan().bold(),
+                ],
+            }),
+            Line::from(match self.countdown {
+                None => vec![],
+                Some(left) => vec!["Countdown: ".into(), format!("{}s", left).red().bold()],
+            }),
+            Line::from(match self.replays_left {
+                None => vec![],
+                Some(0) => vec!["Replays left: ".into(), "0".red().bold()],
+                Some(left) => vec!["Replays left: ".into(), left.to_string().bold()],
+            }),
         ])
         .block(title_block("Game Info"))
         .gray()
@@ -128,365 +690,3038 @@ impl Widget for GameInfo {
     }
 }
 
-#[derive(Debug, Clone)]
-struct Grading {
-    interpret: Option<bool>,
-    title: Option<bool>,
+struct TitlePreview {
+    titles: Vec<(TitleInfo, bool)>,
 }
 
-#[derive(Debug, Clone)]
-struct SongInfo {
-    title: TitleInfo,
-    next: Option<TitleInfo>,
-    grading: Grading,
-}
+impl Widget for TitlePreview {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let missing = self.titles.iter().filter(|(_, exists)| !exists).count();
 
-#[derive(Debug)]
-struct Client {
-    stream: TcpStream,
-    nickname: String,
-}
+        let [list_area, footer] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]).areas(area);
 
-impl Widget for SongInfo {
-    fn render(self, area: Rect, buf: &mut Buffer) {
-        let title_grading = match self.grading.title {
-            None => "not yet graded".gray().bold(),
-            Some(grade) => match grade {
-                true => "correct".green().bold(),
-                false => "incorrect".red().bold(),
-            },
-        };
+        let items: Vec<ListItem> = self
+            .titles
+            .into_iter()
+            .enumerate()
+            .map(|(index, (title, exists))| {
+                let mut line = vec![
+                    format!("{:>3}. ", index + 1).dark_gray(),
+                    title.title.blue(),
+                    " - ".into(),
+                    title.interpret.yellow(),
+                ];
+                if title.warmup {
+                    line.push("  (warm-up)".cyan());
+                }
+                if !exists {
+                    line.push("  (audio file missing)".red().bold());
+                }
+                ListItem::new(Line::from(line))
+            })
+            .collect();
 
-        let interpret_grading = match self.grading.interpret {
-            None => "not yet graded".gray().bold(),
-            Some(grade) => match grade {
-                true => "correct".green().bold(),
-                false => "incorrect".red().bold(),
-            },
+        List::new(items)
+            .block(title_block("Loaded Titles"))
+            .render(list_area, buf);
+
+        let status = if missing == 0 {
+            "All audio files found".green().bold()
+        } else {
+            format!("{} audio files missing", missing).red().bold()
         };
 
+        Paragraph::new(Line::from(vec![
+            status,
+            " - press Enter to start the quiz or q to quit".into(),
+        ]))
+        .block(Block::bordered().gray())
+        .render(footer, buf);
+    }
+}
+
+struct GameOver {
+    titles_correct: usize,
+    interprets_correct: usize,
+    songs_played: usize,
+    total_num: usize,
+    points: u32,
+    disputed: Vec<TitleInfo>,
+    skipped: usize,
+    players: Vec<(String, u32)>,
+    reason: &'static str,
+}
+
+impl Widget for GameOver {
+    fn render(self, area: Rect, buf: &mut Buffer) {
         let mut line_elements = vec![
+            Line::from(self.reason.bold()),
+            Line::from(vec![]),
             Line::from(vec![
-                "Title: ".blue().bold(),
-                self.title.title.as_str().into(),
-                " - ".into(),
-                title_grading,
+                "Songs played: ".into(),
+                format!("{} / {}", self.songs_played, self.total_num)
+                    .yellow()
+                    .bold(),
             ]),
+            Line::from(match self.skipped {
+                0 => vec![],
+                skipped => vec!["Songs skipped: ".into(), skipped.to_string().dark_gray().bold()],
+            }),
             Line::from(vec![
-                "Interpret: ".yellow().bold(),
-                self.title.interpret.as_str().into(),
-                " - ".into(),
-                interpret_grading,
+                "Titles correct: ".into(),
+                self.titles_correct.to_string().green().bold(),
+            ]),
+            Line::from(vec![
+                "Interprets correct: ".into(),
+                self.interprets_correct.to_string().green().bold(),
+            ]),
+            Line::from(vec![
+                "Points: ".into(),
+                self.points.to_string().yellow().bold(),
             ]),
         ];
 
-        if let Some(next) = self.next {
-
+        if !self.players.is_empty() {
             line_elements.push(Line::from(vec![]));
-            line_elements.push(Line::from(vec![
-                "Coming up: ".gray().bold()
-            ]));
-
-            let append_title = vec![
-                "Interpret: ".blue().bold(),
-                next.title.clone().as_str().to_owned().into()
-            ];
-            line_elements.push(append_title.into());
+            line_elements.push(Line::from("Individual rounds:".cyan().bold()));
+            for (nickname, points) in self.players {
+                line_elements.push(Line::from(vec![
+                    format!("{}: ", nickname).into(),
+                    points.to_string().yellow().bold(),
+                ]));
+            }
+        }
 
-            let append_interpret = vec![
-                "Interpret: ".yellow().bold(),
-                next.interpret.clone().as_str().to_owned().into()
-            ];
-            line_elements.push(append_interpret.into());
+        if !self.disputed.is_empty() {
+            line_elements.push(Line::from(vec![]));
+            line_elements.push(Line::from("Disputed, please review:".magenta().bold()));
+            for title in self.disputed {
+                line_elements.push(Line::from(format!(
+                    "{} - {}",
+                    title.title, title.interpret
+                )));
+            }
         }
 
+        line_elements.push(Line::from(vec![]));
+        line_elements.push(Line::from("Press q to quit".dark_gray()));
+
         Paragraph::new(line_elements)
-        .block(title_block("Current Title"))
-        .gray()
+        .block(title_block("Game Over"))
+        .centered()
         .render(area, buf);
     }
 }
 
-#[derive(Debug)]
-struct App {
-    title: u32,
-    exit: bool,
-    playing: bool,
-    transfered: bool,
-    handles: Arc<Mutex<Vec<Client>>>,
-    event_channel: Receiver<AppEvent>,
-    titles: TitleList,
-    current_grading: Grading,
-    grading_history: Vec<Grading>,
-}
-
-impl App {
-    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn Error>> {
-        while !self.exit {
-            terminal.draw(|frame| self.draw(frame))?;
-            self.handle_events()?
-        }
-        Ok(())
-    }
-    fn draw(&self, frame: &mut Frame) {
-        let outer_layout =
-            Layout::vertical(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
-                .split(frame.area());
-
-        let inner_layout = Layout::horizontal(vec![
-            Constraint::Percentage(33),
-            Constraint::Percentage(33),
-            Constraint::Fill(1),
-        ])
-        .split(outer_layout[1]);
+/// Every host key binding, in the order the help overlay lists them.
+const KEY_BINDINGS: [(&str, &str); 30] = [
+    ("t", "Send the current song to all clients"),
+    ("T", "Replay the current song for everyone"),
+    ("p", "Play"),
+    ("o", "Pause"),
+    ("r", "Repeat from the start"),
+    ("Left/Right", "Seek back / forward"),
+    ("c", "Play the chime"),
+    ("h", "Reveal the next clue"),
+    ("k", "Start the countdown, restarting a running one"),
+    ("v", "Reveal the grading"),
+    ("w", "Show the answer without grading"),
+    ("a / s", "Title incorrect / correct"),
+    ("y / x", "Interpret incorrect / correct"),
+    ("d", "Mark the song as disputed"),
+    ("g", "Switch between room and per-player grading"),
+    ("n", "Record the grading and go to the next song"),
+    ("u", "Undo the last recorded grading"),
+    ("l", "Look back at the graded songs"),
+    ("j", "Jump to an upcoming song by its number"),
+    ("e / i", "Correct the title / interpret of the current song"),
+    ("m", "Shuffle the remaining songs"),
+    ("R", "Pull all clients back in sync"),
+    ("F", "Skip to the results"),
+    ("Up/Down", "Select a client"),
+    ("/", "Filter the client list"),
+    ("Esc", "Clear the filter while filtering"),
+    ("Enter", "Start the quiz"),
+    ("?", "Show this help"),
+    ("q", "Quit and tell clients the quiz ended"),
+    ("any key", "Close this help"),
+];
 
-        let connection_info = ConnectionInfo {
-            active_clients: self.handles.lock().unwrap().len() as u8,
-            transfered: self.transfered,
-            playing: self.playing,
-        };
+struct HelpOverlay;
 
-        let titles_correct = self
-            .grading_history
+impl Widget for HelpOverlay {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lines: Vec<Line> = KEY_BINDINGS
             .iter()
-            .filter(|grad| grad.title.is_some_and(|val| val))
-            .count() as u8;
+            .map(|(key, action)| {
+                Line::from(vec![
+                    format!(" {:>10}  ", key).yellow().bold(),
+                    (*action).into(),
+                ])
+            })
+            .collect();
 
-        let interprets_correct = self
-            .grading_history
-            .iter()
-            .filter(|grad| grad.interpret.is_some_and(|val| val))
-            .count() as u8;
+        Paragraph::new(lines)
+            .block(title_block("Keys"))
+            .render(area, buf);
+    }
+}
 
-        let game_info = GameInfo {
-            titles_correct,
-            interprets_correct,
-            current_index: self.title as u8,
-            total_num: self.titles.titles.len() as u8,
-        };
+/// Every recorded round with its grading, for looking back at earlier songs.
+struct GradingHistory {
+    rounds: Vec<(TitleInfo, Grading)>,
+    grade_style: GradeStyle,
+    scoring: Scoring,
+}
 
-        let next = if (self.title as usize) < self.titles.titles.len() - 1 {
-            Some(self.titles.titles[self.title as usize + 1].clone()) 
-        } else {
-            None
-        };
+impl ratatui::widgets::StatefulWidget for GradingHistory {
+    type State = ListState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut ListState) {
+        let items: Vec<ListItem> = self
+            .rounds
+            .into_iter()
+            .enumerate()
+            .map(|(index, (title, grading))| {
+                let mut line = vec![
+                    format!("{:>3}. ", index + 1).dark_gray(),
+                    format!("{} - {}  ", title.title, title.interpret).into(),
+                    self.grade_style
+                        .span(grading.title, self.scoring.title_points),
+                    " / ".into(),
+                    self.grade_style
+                        .span(grading.interpret, self.scoring.interpret_points),
+                ];
+                if grading.disputed {
+                    line.push("  disputed".magenta().bold());
+                }
+                if title.warmup {
+                    line.push("  warm-up".cyan());
+                }
+                ListItem::new(Line::from(line))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(title_block("Grading History - [l] close"))
+            .highlight_symbol("> ")
+            .highlight_style(Style::new().yellow().bold());
+        ratatui::widgets::StatefulWidget::render(list, area, buf, state);
+    }
+}
+
+struct Playlist {
+    played: Vec<TitleInfo>,
+    current: TitleInfo,
+    /// Upcoming songs with their number, the one the jump prompt takes
+    remaining: Vec<(usize, TitleInfo)>,
+    shuffle: bool,
+}
+
+impl Widget for Playlist {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let mut line_elements = vec![Line::from(vec![
+            "Played: ".into(),
+            self.played.len().to_string().yellow().bold(),
+            "  Remaining: ".into(),
+            self.remaining.len().to_string().yellow().bold(),
+        ])];
+
+        for title in self.played {
+            line_elements.push(
+                Line::from(format!("  {} - {}", title.title, title.interpret))
+                    .dark_gray()
+                    .crossed_out(),
+            );
+        }
+
+        line_elements.push(
+            Line::from(format!(
+                "> {} - {}",
+                self.current.title, self.current.interpret
+            ))
+            .green()
+            .bold(),
+        );
+
+        for (number, title) in self.remaining {
+            line_elements.push(Line::from(format!(
+                "  {}. {} - {}",
+                number, title.title, title.interpret
+            )));
+        }
+
+        let block_title = if self.shuffle {
+            "Songs (shuffled)"
+        } else {
+            "Songs"
+        };
+
+        Paragraph::new(line_elements)
+            .block(title_block(block_title))
+            .gray()
+            .render(area, buf);
+    }
+}
+
+/// Snapshot of the host screen sent to mirror clients. The answer stays
+/// hidden until the host reveals it.
+#[derive(Serialize, Debug)]
+struct HostState {
+    song: usize,
+    total: usize,
+    titles_correct: usize,
+    interprets_correct: usize,
+    disputed: usize,
+    points: u32,
+    elapsed_secs: u64,
+    time_left_secs: Option<u64>,
+    answer: Option<(String, String)>,
+    players: Vec<String>,
+    game_over: Option<String>,
+}
+
+/// What the status endpoint serves, the mirror view plus every player's points.
+#[derive(Serialize, Debug)]
+struct Status {
+    #[serde(flatten)]
+    game: HostState,
+    scores: HashMap<String, u32>,
+}
+
+/// The authoritative song and play state, sent to pull drifted clients back.
+#[derive(Serialize, Debug)]
+struct Resync {
+    song: usize,
+    total: usize,
+    playing: bool,
+    transfered: bool,
+}
+
+/// A player's final result, sent to every player when the game ends.
+#[derive(Serialize, Debug)]
+struct Summary {
+    /// The player's own points once per-player grading was used, otherwise the room's
+    points: u32,
+    possible: u32,
+    songs: usize,
+}
+
+/// The plain answer for an ungraded reveal.
+#[derive(Serialize, Debug)]
+struct Answer {
+    title: String,
+    interpret: String,
+}
+
+#[derive(Serialize, Debug)]
+struct TitleGrading {
+    title: String,
+    interpret: String,
+    title_correct: Option<bool>,
+    interpret_correct: Option<bool>,
+    title_points: u32,
+    interpret_points: u32,
+    reveal_delay: u64,
+}
+
+/// Everything the quiz produced, written on quit so organizers can review or publish it.
+#[derive(Serialize, Debug)]
+struct QuizResults<'a> {
+    quiz: Option<&'a str>,
+    finished_at: u64,
+    points: u32,
+    possible: u32,
+    songs: Vec<SongResult<'a>>,
+    /// Per-player points, best first, empty unless per-player grading was used
+    players: Vec<(String, u32)>,
+}
+
+#[derive(Serialize, Debug)]
+struct SongResult<'a> {
+    title: &'a str,
+    interpret: &'a str,
+    warmup: bool,
+    title_correct: Option<bool>,
+    interpret_correct: Option<bool>,
+    disputed: bool,
+    graded_at: u64,
+}
+
+/// One line of the audit log, written when a song's grading is recorded.
+#[derive(Serialize, Debug)]
+struct AuditEntry<'a> {
+    song: usize,
+    title: &'a str,
+    interpret: &'a str,
+    title_correct: Option<bool>,
+    interpret_correct: Option<bool>,
+    disputed: bool,
+    /// Set when the host took this grading back
+    undone: bool,
+    timestamp: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Grading {
+    interpret: Option<bool>,
+    title: Option<bool>,
+    disputed: bool,
+}
+
+impl Grading {
+    fn is_complete(&self) -> bool {
+        self.title.is_some() && self.interpret.is_some()
+    }
+}
+
+struct GradingKeys {
+    grading: Grading,
+    title: String,
+}
+
+impl GradingKeys {
+    fn toggles(grade: Option<bool>, wrong_key: char, right_key: char) -> Vec<Span<'static>> {
+        let wrong = format!(" [{}] incorrect ", wrong_key);
+        let right = format!(" [{}] correct ", right_key);
+
+        vec![
+            match grade {
+                Some(false) => wrong.black().on_red().bold(),
+                _ => wrong.dark_gray(),
+            },
+            " ".into(),
+            match grade {
+                Some(true) => right.black().on_green().bold(),
+                _ => right.dark_gray(),
+            },
+        ]
+    }
+}
+
+impl Widget for GradingKeys {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let mut title_line = vec!["Title:     ".blue().bold()];
+        title_line.extend(GradingKeys::toggles(self.grading.title, 'a', 's'));
+
+        let mut interpret_line = vec!["Interpret: ".yellow().bold()];
+        interpret_line.extend(GradingKeys::toggles(self.grading.interpret, 'y', 'x'));
+
+        Paragraph::new(vec![Line::from(title_line), Line::from(interpret_line)])
+            .block(title_block(&self.title))
+            .gray()
+            .render(area, buf);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SongInfo {
+    title: TitleInfo,
+    next: Option<TitleInfo>,
+    grading: Grading,
+    grade_style: GradeStyle,
+    scoring: Scoring,
+    clues_revealed: usize,
+}
+
+/// A client's stream to write a song to, with its id and write lock.
+type Writer = (usize, TcpStream, Arc<Mutex<()>>);
+
+/// A command held back during a transfer, with the payloads it had when it was sent.
+/// Clients with neither their own payload nor a shared one are skipped.
+#[derive(Debug)]
+struct Deferred {
+    command: Command,
+    payload: Option<Vec<u8>>,
+    player_payloads: HashMap<String, Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct Client {
+    id: usize,
+    stream: TcpStream,
+    /// Where the client connected from, if the socket could tell
+    address: Option<SocketAddr>,
+    /// Hash of the song the client held when it connected, until the next transfer to it
+    song_hash: Option<u64>,
+    nickname: String,
+    color: Option<Color>,
+    mirror: bool,
+    last_activity: Instant,
+    /// Held for every message written to the stream, so a preload chunk and a command
+    /// written from another thread never interleave
+    write_lock: Arc<Mutex<()>>,
+    /// A resend is running, commands skip the client and it is resynced afterwards
+    receiving: bool,
+}
+
+/// What a client introduces itself with. Older clients send only the bare
+/// nickname instead of this JSON object.
+#[derive(Deserialize, Debug)]
+struct Handshake {
+    nickname: String,
+    #[serde(default)]
+    color: Option<String>,
+    /// Wants the host screen instead of playing along
+    #[serde(default)]
+    mirror: bool,
+    /// Hash of the song a reconnecting client still holds
+    #[serde(default)]
+    song_hash: Option<u64>,
+}
+
+impl Widget for SongInfo {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let title_grading = self
+            .grade_style
+            .span(self.grading.title, self.scoring.title_points);
+
+        let interpret_grading = self
+            .grade_style
+            .span(self.grading.interpret, self.scoring.interpret_points);
+
+        let mut line_elements = vec![
+            Line::from(vec![
+                "Title: ".blue().bold(),
+                self.title.title.as_str().into(),
+                " - ".into(),
+                title_grading,
+            ]),
+            Line::from(vec![
+                "Interpret: ".yellow().bold(),
+                self.title.interpret.as_str().into(),
+                " - ".into(),
+                interpret_grading,
+            ]),
+        ];
+
+        if self.grading.disputed {
+            line_elements.push(Line::from(
+                "Disputed - flagged for review".magenta().bold(),
+            ));
+        }
+
+        if self.title.warmup {
+            line_elements.push(Line::from("Warm-up - not scored".cyan().bold()));
+        }
+
+        if !self.title.clues.is_empty() {
+            line_elements.push(Line::from(vec![format!(
+                "Clues revealed: {}/{}",
+                self.clues_revealed,
+                self.title.clues.len()
+            )
+            .cyan()
+            .bold()]));
+            if let Some(clue) = self.title.clues.get(self.clues_revealed) {
+                line_elements.push(Line::from(vec![
+                    "Next clue: ".gray().bold(),
+                    clue.as_str().into(),
+                ]));
+            }
+        }
+
+        if let Some(next) = self.next {
+
+            line_elements.push(Line::from(vec![]));
+            line_elements.push(Line::from(vec![
+                "Coming up: ".gray().bold()
+            ]));
+
+            let append_title = vec![
+                "Interpret: ".blue().bold(),
+                next.title.clone().as_str().to_owned().into()
+            ];
+            line_elements.push(append_title.into());
+
+            let append_interpret = vec![
+                "Interpret: ".yellow().bold(),
+                next.interpret.clone().as_str().to_owned().into()
+            ];
+            line_elements.push(append_interpret.into());
+        } else {
+            line_elements.push(Line::from(vec![]));
+            line_elements.push(Line::from(vec!["Coming up: ".gray().bold(), "—".into()]));
+        }
+
+        Paragraph::new(line_elements)
+        .block(title_block("Current Title"))
+        .gray()
+        .render(area, buf);
+    }
+}
+
+#[derive(Debug)]
+struct App {
+    title: usize,
+    order: Vec<usize>,
+    position: usize,
+    shuffle: bool,
+    exit: bool,
+    playing: bool,
+    transfered: bool,
+    handles: Arc<Mutex<Vec<Client>>>,
+    waiting: Arc<Mutex<Vec<Client>>>,
+    /// Progress of the running transfer, other commands wait until it is done
+    transfer: Option<(u64, u64)>,
+    /// Start playback again once the running transfer is done
+    play_after_transfer: bool,
+    /// Commands held back during the transfer, sent once it is done
+    deferred: Vec<Deferred>,
+    /// Only the latest play state is worth sending after the transfer, it goes out every tick
+    deferred_play_state: Option<bool>,
+    max_clients: Option<usize>,
+    event_channel: Receiver<AppEvent>,
+    event_sender: mpsc::Sender<AppEvent>,
+    titles: TitleList,
+    current_grading: Grading,
+    grading_history: Vec<Grading>,
+    /// Unix seconds each entry of `grading_history` was recorded at
+    graded_at: Vec<u64>,
+    /// The per-player gradings folded into the points with each entry of `grading_history`
+    player_grading_history: Vec<HashMap<String, Grading>>,
+    grading_mode: GradingMode,
+    /// Gradings of the current song in per-player mode, keyed by nickname
+    player_gradings: HashMap<String, Grading>,
+    player_points: HashMap<String, u32>,
+    /// Latest JSON for the status endpoint, only kept while it runs
+    status: Option<Arc<Mutex<String>>>,
+    scoring: Scoring,
+    grade_style: GradeStyle,
+    input_warning: Option<String>,
+    /// Short lived message for the host, with when it was raised
+    notice: Option<(&'static str, Instant)>,
+    clock: GameClock,
+    timer_follows_playback: bool,
+    max_duration: Option<Duration>,
+    auto_advance: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    revealed_at: Option<Instant>,
+    game_over: Option<&'static str>,
+    confirmed: bool,
+    client_filter: String,
+    /// Song number being typed into the jump prompt, keys go to it while open
+    jump: Option<String>,
+    /// Correction being typed for the current song, keys go to it while open
+    edit: Option<(EditField, String)>,
+    /// Where corrections are written back to, if they should be
+    titles_path: Option<PathBuf>,
+    countdown_secs: u64,
+    /// Bumped by every countdown start or cancel, ticks of older ones are ignored
+    countdown_generation: u64,
+    /// Seconds left of the running countdown
+    countdown: Option<u64>,
+    max_replays: Option<u32>,
+    /// Repeats of the current song so far
+    replays_used: u32,
+    filtering: bool,
+    show_help: bool,
+    /// Selected round while the grading history is shown
+    history: Option<usize>,
+    selected_client: Option<usize>,
+    clues_revealed: usize,
+    no_audio: bool,
+    preload: bool,
+    /// Set to stop the running preload, it checks between chunks, and its worker
+    preload_worker: Option<(Arc<AtomicBool>, thread::JoinHandle<()>)>,
+    /// Title the clients were sent ahead of time
+    preloaded: Option<usize>,
+    /// Hash of every song file hashed so far, filled in the background at startup
+    song_hashes: Arc<Mutex<HashMap<PathBuf, u64>>>,
+    revealed: bool,
+    export_m3u: Option<PathBuf>,
+    audit_log: Option<PathBuf>,
+    results: Option<PathBuf>,
+    quiz_name: Option<String>,
+    skipped: Vec<usize>,
+    /// Who buzzed for the current song, in arrival order
+    buzzes: Vec<(String, Instant)>,
+    /// Latest guess of every player for the current song
+    guesses: HashMap<String, String>,
+    grader: Option<String>,
+    /// Players whose guess for the current song the grader is still working on
+    pending_grades: HashSet<String>,
+    /// Bumped whenever another song comes up, so late verdicts are not applied to it
+    song_generation: u64,
+    music_dir: PathBuf,
+    extensions: Vec<String>,
+    transfer_threads: usize,
+}
+
+impl App {
+    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), ServerError> {
+        self.hash_songs();
+        let outcome = self.event_loop(terminal);
+        self.announce_shutdown();
+
+        // Also after a failure, the results so far are worth keeping.
+        if let Some(path) = &self.results {
+            match self.write_results(path) {
+                Ok(()) => info!("Wrote results to {}", path.display()),
+                Err(err) => error!("Could not write results to {}: {}", path.display(), err),
+            }
+        }
+        outcome
+    }
+    /// Hashes every song file once on a worker thread, so a reconnecting client's song can
+    /// be checked against it without reading the file again.
+    fn hash_songs(&self) {
+        if self.no_audio {
+            return;
+        }
+        let paths: Vec<PathBuf> = (0..self.titles.titles.len())
+            .filter_map(|index| self.song_path_for(index))
+            .collect();
+        let song_hashes = self.song_hashes.clone();
+        thread::spawn(move || {
+            for path in paths {
+                match File::open(&path).and_then(song_hash) {
+                    Ok(hash) => {
+                        song_hashes.lock().unwrap().insert(path, hash);
+                    }
+                    Err(err) => warn!("Could not hash {}: {}", path.display(), err),
+                }
+            }
+        });
+    }
+    /// Tells everyone the quiz is over, so clients show their result instead of reconnecting.
+    fn announce_shutdown(&mut self) {
+        if self.transfer.is_some() {
+            self.await_transfer();
+        }
+        if self.game_over.is_none() {
+            self.send_summary();
+        }
+        self.send_command(Command::Shutdown);
+        for client in self.waiting.lock().unwrap().iter_mut() {
+            let _ = write_framed_message(&mut client.stream, &Command::Shutdown, &[]);
+        }
+    }
+    /// Waits a while for the running transfer, the rest of the events no longer matter.
+    fn await_transfer(&mut self) {
+        let deadline = Instant::now() + SHUTDOWN_WAIT;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.event_channel.recv_timeout(left) {
+                Ok(AppEvent::TransferFinished(failed)) => {
+                    self.finish_transfer(failed);
+                    return;
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    warn!("Transfer still running, clients are not told about the shutdown");
+                    return;
+                }
+            }
+        }
+    }
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<(), ServerError> {
+        while !self.exit {
+            terminal.draw(|frame| self.draw(frame))?;
+            self.handle_events()?
+        }
+        Ok(())
+    }
+    fn write_results(&self, path: &Path) -> Result<(), ServerError> {
+        let (titles_correct, interprets_correct) = self.tally();
+        let scored = self.scored_history().count();
+        let songs = self
+            .grading_history
+            .iter()
+            .zip(&self.graded_at)
+            .enumerate()
+            .map(|(index, (grading, &graded_at))| {
+                let title = &self.titles.titles[self.order[index]];
+                SongResult {
+                    title: &title.title,
+                    interpret: &title.interpret,
+                    warmup: title.warmup,
+                    title_correct: grading.title,
+                    interpret_correct: grading.interpret,
+                    disputed: grading.disputed,
+                    graded_at,
+                }
+            })
+            .collect();
+
+        let results = QuizResults {
+            quiz: self.quiz_name.as_deref(),
+            finished_at: unix_time(),
+            points: self.scoring.points(titles_correct, interprets_correct),
+            possible: self.scoring.points(scored, scored),
+            songs,
+            players: self.player_standings(),
+        };
+        fs::write(path, serde_json::to_string_pretty(&results)?)?;
+        Ok(())
+    }
+    fn draw(&self, frame: &mut Frame) {
+        let mut area = frame.area();
+
+        if let Some(warning) = &self.input_warning {
+            let [banner, rest] =
+                Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(area);
+            Paragraph::new(warning.as_str())
+                .white()
+                .on_red()
+                .bold()
+                .centered()
+                .render(banner, frame.buffer_mut());
+            area = rest;
+        }
+
+        let notice = self.notice.filter(|(_, at)| at.elapsed() < NOTICE_DURATION);
+        if let Some((notice, _)) = notice {
+            let [banner, rest] =
+                Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(area);
+            Paragraph::new(notice)
+                .black()
+                .on_yellow()
+                .bold()
+                .centered()
+                .render(banner, frame.buffer_mut());
+            area = rest;
+        }
+
+        if !self.confirmed {
+            let preview = TitlePreview {
+                titles: (0..self.titles.titles.len())
+                    .map(|index| {
+                        let exists =
+                            self.no_audio || self.song_path_for(index).is_some();
+                        (self.titles.titles[index].clone(), exists)
+                    })
+                    .collect(),
+            };
+            frame.render_widget(preview, area);
+            return;
+        }
+
+        let outer_layout =
+            Layout::vertical(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(area);
+
+        let upper_layout =
+            Layout::horizontal(vec![Constraint::Percentage(66), Constraint::Fill(1)])
+                .split(outer_layout[0]);
+
+        let song_layout = Layout::vertical(vec![Constraint::Fill(1), Constraint::Length(4)])
+            .split(upper_layout[0]);
+
+        let inner_layout = Layout::horizontal(vec![
+            Constraint::Percentage(33),
+            Constraint::Percentage(33),
+            Constraint::Fill(1),
+        ])
+        .split(outer_layout[1]);
+
+        let connection_info = ConnectionInfo {
+            active_clients: self.handles.lock().unwrap().len(),
+            waiting_clients: self.waiting.lock().unwrap().len(),
+            transfered: self.transfered,
+            transfer: self.transfer,
+            playing: self.playing,
+            audio: !self.no_audio,
+        };
+
+        let (titles_correct, interprets_correct) = self.tally();
+
+        let game_info = GameInfo {
+            titles_correct,
+            interprets_correct,
+            current_index: self.scored_history().count(),
+            total_num: self.scored_total(),
+            disputed: self.disputed_songs().len(),
+            scoring: self.scoring,
+            elapsed: self.clock.elapsed(),
+            time_left: self
+                .max_duration
+                .map(|max| max.saturating_sub(self.clock.elapsed())),
+            advance_in: self.advance_in(),
+            countdown: self.countdown,
+            replays_left: self
+                .max_replays
+                .map(|max| max.saturating_sub(self.replays_used)),
+        };
+
+        // Checked lookups, a bad index leaves the panel blank instead of taking the host down.
+        let titles = &self.titles.titles;
+        let current = titles.get(self.title).cloned().unwrap_or_default();
+        let next = self
+            .order
+            .get(self.position + 1)
+            .and_then(|&index| titles.get(index))
+            .cloned();
 
         let song_info = SongInfo {
-            title: self.titles.titles[self.title as usize].clone(),
+            title: current.clone(),
             next,
             grading: self.current_grading.clone(),
+            grade_style: self.grade_style,
+            scoring: self.scoring,
+            clues_revealed: self.clues_revealed,
+        };
+
+        let playlist = Playlist {
+            played: self.order[..self.position]
+                .iter()
+                .map(|&index| self.titles.titles[index].clone())
+                .collect(),
+            current,
+            remaining: self.order[self.position + 1..]
+                .iter()
+                .map(|&index| (index + 1, self.titles.titles[index].clone()))
+                .collect(),
+            shuffle: self.shuffle,
+        };
+
+        let grading_keys = match self.grading_mode {
+            GradingMode::Room => GradingKeys {
+                grading: self.current_grading.clone(),
+                title: "Grading (room)".to_string(),
+            },
+            GradingMode::Player => match self.selected_nickname() {
+                Some(nickname) => GradingKeys {
+                    grading: self.player_gradings.get(&nickname).cloned().unwrap_or_default(),
+                    title: format!("Grading ({})", nickname),
+                },
+                None => GradingKeys {
+                    grading: Grading::default(),
+                    title: "Grading (select a player)".to_string(),
+                },
+            },
+        };
+
+        frame.render_widget(song_info, song_layout[0]);
+        frame.render_widget(grading_keys, song_layout[1]);
+        frame.render_widget(playlist, upper_layout[1]);
+        let connection_layout =
+            Layout::vertical(vec![Constraint::Percentage(50), Constraint::Fill(1)])
+                .split(inner_layout[0]);
+
+        let first_buzz = self.buzzes.first().map(|(_, at)| *at);
+        let buzz_order = BuzzOrder {
+            buzzes: self
+                .buzzes
+                .iter()
+                .map(|(nickname, at)| {
+                    let behind = first_buzz.map_or(Duration::ZERO, |first| *at - first);
+                    (nickname.clone(), behind)
+                })
+                .collect(),
+        };
+
+        frame.render_widget(connection_info, connection_layout[0]);
+        frame.render_widget(buzz_order, connection_layout[1]);
+        frame.render_widget(game_info, inner_layout[1]);
+
+        let nicknames: Vec<ListItem> = self
+            .filtered_clients()
+            .into_iter()
+            .map(|(_, nickname, color, address)| {
+                let mut label = match self.player_points.get(&nickname) {
+                    Some(points) => format!("{} ({})", nickname, points),
+                    None => nickname.clone(),
+                };
+                if let Some(guess) = self.guesses.get(&nickname) {
+                    label.push_str(&format!(": {}", guess));
+                }
+                // Once someone buzzed, the list shows who to call on.
+                let buzz = self.buzzes.iter().position(|(buzzed, _)| *buzzed == nickname);
+                let address = address.map_or("unknown".to_string(), |addr| addr.to_string());
+                let line = |label: String| {
+                    Line::from(vec![label.into(), " ".into(), address.dark_gray().not_bold()])
+                };
+                let item = match buzz {
+                    Some(order) => ListItem::new(line(format!("{}. {}", order + 1, label))).bold(),
+                    None => ListItem::new(line(label)),
+                };
+                match (buzz, color) {
+                    (Some(0), _) => item.black().on_yellow(),
+                    (None, _) if !self.buzzes.is_empty() => item.dark_gray(),
+                    (_, Some(color)) => item.fg(color),
+                    (_, None) => item,
+                }
+            })
+            .collect();
+
+        let clients_title = if self.filtering || !self.client_filter.is_empty() {
+            let cursor = if self.filtering { "_" } else { "" };
+            format!("Clients /{}{}", self.client_filter, cursor)
+        } else {
+            "Clients".to_string()
+        };
+
+        let mut list_state = ListState::default()
+            .with_selected(self.selected_client.filter(|_| !nicknames.is_empty()));
+
+        frame.render_stateful_widget(
+            List::new(nicknames)
+                .block(title_block(&clients_title))
+                .highlight_symbol("> ")
+                .highlight_style(Style::new().yellow().bold()),
+            inner_layout[2],
+            &mut list_state,
+        );
+
+        if let Some(reason) = self.game_over {
+            let game_over = GameOver {
+                titles_correct,
+                interprets_correct,
+                songs_played: self.scored_history().count(),
+                total_num: self.scored_total(),
+                points: self.scoring.points(titles_correct, interprets_correct),
+                disputed: self.disputed_songs(),
+                skipped: self.skipped.len(),
+                players: self.player_standings(),
+                reason,
+            };
+
+            let popup = popup_area(area, 40, 50);
+            frame.render_widget(Clear, popup);
+            frame.render_widget(game_over, popup);
+        }
+
+        if let Some(jump) = &self.jump {
+            let popup = popup_area(area, 30, 15);
+            frame.render_widget(Clear, popup);
+            Paragraph::new(vec![
+                Line::from(vec![
+                    "Song number: ".into(),
+                    format!("{}_", jump).yellow().bold(),
+                ]),
+                Line::from("[Enter] jump  [Esc] cancel".dark_gray()),
+            ])
+            .block(title_block("Jump To Song"))
+            .render(popup, frame.buffer_mut());
+        }
+
+        if let Some((field, text)) = &self.edit {
+            let (label, title) = match field {
+                EditField::Title => ("Title: ", "Edit Title"),
+                EditField::Interpret => ("Interpret: ", "Edit Interpret"),
+            };
+            let popup = popup_area(area, 50, 15);
+            frame.render_widget(Clear, popup);
+            Paragraph::new(vec![
+                Line::from(vec![label.into(), format!("{}_", text).yellow().bold()]),
+                Line::from("[Enter] save  [Esc] cancel".dark_gray()),
+            ])
+            .block(title_block(title))
+            .render(popup, frame.buffer_mut());
+        }
+
+        if let Some(selected) = self.history {
+            let history = GradingHistory {
+                rounds: self
+                    .grading_history
+                    .iter()
+                    .enumerate()
+                    .map(|(index, grading)| {
+                        let title = &self.titles.titles[self.order[index]];
+                        (title.clone(), grading.clone())
+                    })
+                    .collect(),
+                grade_style: self.grade_style,
+                scoring: self.scoring,
+            };
+            let mut state = ListState::default().with_selected(Some(selected));
+            let popup = popup_area(area, 70, 80);
+            frame.render_widget(Clear, popup);
+            frame.render_stateful_widget(history, popup, &mut state);
+        }
+
+        if self.show_help {
+            let popup = popup_area(area, 60, 80);
+            frame.render_widget(Clear, popup);
+            frame.render_widget(HelpOverlay, popup);
+        }
+    }
+    /// Recorded gradings of the songs that count, with their index into the history
+    fn scored_history(&self) -> impl Iterator<Item = (usize, &Grading)> {
+        self.grading_history
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.titles.titles[self.order[*index]].warmup)
+    }
+    fn scored_total(&self) -> usize {
+        self.titles.titles.iter().filter(|title| !title.warmup).count()
+    }
+    fn is_warmup(&self) -> bool {
+        self.titles.titles[self.title].warmup
+    }
+    fn tally(&self) -> (usize, usize) {
+        let titles_correct = self
+            .scored_history()
+            .filter(|(_, grad)| !grad.disputed && grad.title.is_some_and(|val| val))
+            .count();
+
+        let interprets_correct = self
+            .scored_history()
+            .filter(|(_, grad)| !grad.disputed && grad.interpret.is_some_and(|val| val))
+            .count();
+
+        (titles_correct, interprets_correct)
+    }
+    /// Ids, nicknames and colors of the clients matching the current filter
+    fn filtered_clients(&self) -> Vec<(usize, String, Option<Color>, Option<SocketAddr>)> {
+        let filter = self.client_filter.to_lowercase();
+        self.handles
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|client| client.nickname.to_lowercase().contains(&filter))
+            .map(|client| {
+                (
+                    client.id,
+                    client.nickname.clone(),
+                    client.color,
+                    client.address,
+                )
+            })
+            .collect()
+    }
+    fn selected_nickname(&self) -> Option<String> {
+        let index = self.selected_client?;
+        self.filtered_clients()
+            .into_iter()
+            .nth(index)
+            .map(|(_, nickname, _, _)| nickname)
+    }
+    /// Per-player points, best first
+    fn player_standings(&self) -> Vec<(String, u32)> {
+        let mut standings: Vec<(String, u32)> = self
+            .player_points
+            .iter()
+            .map(|(nickname, &points)| (nickname.clone(), points))
+            .collect();
+        standings.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        standings
+    }
+    fn move_client_selection(&mut self, up: bool) {
+        let count = self.filtered_clients().len();
+        if count == 0 {
+            self.selected_client = None;
+            return;
+        }
+        self.selected_client = Some(match self.selected_client {
+            None => 0,
+            Some(index) if up => index.saturating_sub(1),
+            Some(index) => (index + 1).min(count - 1),
+        });
+    }
+    fn match_filter_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char(c) => self.client_filter.push(c),
+            KeyCode::Backspace => {
+                self.client_filter.pop();
+            }
+            KeyCode::Enter => self.filtering = false,
+            KeyCode::Esc => {
+                self.client_filter.clear();
+                self.filtering = false;
+            }
+            _ => return,
+        }
+        self.selected_client = None;
+    }
+    fn disputed_songs(&self) -> Vec<TitleInfo> {
+        self.scored_history()
+            .filter(|(_, grad)| grad.disputed)
+            .map(|(index, _)| self.titles.titles[self.order[index]].clone())
+            .collect()
+    }
+    fn handle_events(&mut self) -> Result<(), ServerError> {
+        match self.event_channel.recv()? {
+            AppEvent::ClientUpdate => {}
+            // Heartbeats only keep the socket read alive, an abandoned client still idles.
+            AppEvent::ClientRequest(id, request) => match request {
+                Request::Resend => {
+                    self.touch_client(id);
+                    self.resend(id);
+                }
+                Request::Leave => self.remove_client(id),
+                Request::Heartbeat | Request::Buzz | Request::Guess => {}
+            },
+            AppEvent::ClientLeft(id) => self.remove_client(id),
+            AppEvent::Buzz { id, nickname, at } => {
+                self.touch_client(id);
+                self.buzz(id, nickname, at);
+            }
+            AppEvent::Guess {
+                id,
+                nickname,
+                guess,
+            } => {
+                self.touch_client(id);
+                self.guess(id, nickname, guess);
+            }
+            AppEvent::Countdown { generation, left } => self.countdown_tick(generation, left),
+            AppEvent::AutoGraded {
+                nickname,
+                generation,
+                grading,
+            } => {
+                // A jump can put another song at the same position, so go by the song.
+                if generation == self.song_generation && self.pending_grades.remove(&nickname) {
+                    if let Some(grading) = grading {
+                        let current = self.player_gradings.entry(nickname).or_default();
+                        current.title = grading.title.or(current.title);
+                        current.interpret = grading.interpret.or(current.interpret);
+                    }
+                }
+            }
+            AppEvent::InputError(err) => {
+                self.input_warning = Some(format!("Keyboard input failing, retrying: {}", err));
+            }
+            AppEvent::InputRecovered => {
+                self.input_warning = None;
+            }
+            AppEvent::Tick => {
+                self.reap_idle_clients();
+                self.admit_waiting();
+                self.check_auto_advance();
+                self.check_duration();
+                self.sync_play_state();
+                self.broadcast_host_state();
+                self.publish_status();
+            }
+            AppEvent::TransferProgress(sent, total) => {
+                self.transfer = Some((sent, total));
+            }
+            AppEvent::TransferFinished(failed) => {
+                self.finish_transfer(failed);
+                if self.play_after_transfer {
+                    self.play_after_transfer = false;
+                    self.play();
+                }
+                // A resend leaves a finished preload in place.
+                if self.preloaded.is_none() {
+                    self.start_preload();
+                }
+            }
+            AppEvent::ResendFinished(id, sent) => self.finish_resend(id, sent),
+            AppEvent::PreloadFinished(title) => {
+                info!("Preloaded song {}", title + 1);
+                self.preloaded = Some(title);
+            }
+            AppEvent::InputLost => {
+                self.input_warning = Some(
+                    "Keyboard input lost! Restart the server to regain control.".to_string(),
+                );
+            }
+            AppEvent::CrossTerm(event) => match event {
+                Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
+                    self.match_key_event(key_event);
+                }
+                _ => {}
+            },
+        }
+
+        if self.confirmed && self.timer_follows_playback {
+            self.clock.set_running(self.playing && self.game_over.is_none());
+        }
+        Ok(())
+    }
+    fn match_key_event(&mut self, event: KeyEvent) {
+        if self.show_help {
+            self.show_help = false;
+            return;
+        }
+
+        if !self.confirmed {
+            match event.code {
+                KeyCode::Enter => {
+                    self.confirmed = true;
+                    self.clock.set_running(true);
+                }
+                KeyCode::Char('q') => {
+                    self.exit = true;
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        if self.filtering {
+            self.match_filter_key(event.code);
+            return;
+        }
+
+        if self.jump.is_some() {
+            self.match_jump_key(event.code);
+            return;
+        }
+
+        if self.edit.is_some() {
+            self.match_edit_key(event.code);
+            return;
+        }
+
+        if self.history.is_some() {
+            self.match_history_key(event.code);
+            return;
+        }
+
+        match event.code {
+            KeyCode::Char('/') => {
+                self.filtering = true;
+            }
+            KeyCode::Up => {
+                self.move_client_selection(true);
+            }
+            KeyCode::Down => {
+                self.move_client_selection(false);
+            }
+            KeyCode::Left => {
+                self.seek(Command::SeekBack);
+            }
+            KeyCode::Right => {
+                self.seek(Command::SeekForward);
+            }
+            KeyCode::Char('o') => {
+                self.pause();
+            }
+            KeyCode::Char('p') => {
+                self.play();
+            }
+            KeyCode::Char('t') if !self.transfered && !self.no_audio && self.game_over.is_none() => {
+                self.transfered = true;
+                self.transfer_file();
+            }
+            KeyCode::Char('T') if self.transfered && !self.no_audio && self.game_over.is_none() => {
+                self.replay_for_everyone();
+            }
+            KeyCode::Char('a') => {
+                self.grade_title(false);
+            }
+            KeyCode::Char('s') => {
+                self.grade_title(true);
+            }
+            KeyCode::Char('y') => {
+                self.grade_interpret(false);
+            }
+            KeyCode::Char('x') => {
+                self.grade_interpret(true);
+            }
+            KeyCode::Char('n') => {
+                self.next();
+            }
+            KeyCode::Char('r') => {
+                self.repeat();
+            }
+            KeyCode::Char('m') => {
+                self.toggle_shuffle();
+            }
+            KeyCode::Char('v') => {
+                self.reveal();
+            }
+            KeyCode::Char('c') => {
+                self.chime();
+            }
+            KeyCode::Char('h') => {
+                self.reveal_clue();
+            }
+            KeyCode::Char('R') => {
+                self.resync();
+            }
+            KeyCode::Char('w') => {
+                self.show_answer();
+            }
+            KeyCode::Char('F') => {
+                self.skip_to_results();
+            }
+            KeyCode::Char('g') => {
+                self.toggle_grading_mode();
+            }
+            KeyCode::Char('d') => {
+                self.current_grading.disputed = !self.current_grading.disputed;
+            }
+            KeyCode::Char('u') => {
+                self.undo_grading();
+            }
+            KeyCode::Char('k') => {
+                self.start_countdown();
+            }
+            KeyCode::Char('j') if self.game_over.is_none() => {
+                self.jump = Some(String::new());
+            }
+            KeyCode::Char('l') => {
+                // Opens on the latest round, the one most likely in question.
+                self.history = Some(self.grading_history.len().saturating_sub(1));
+            }
+            KeyCode::Char('e') => {
+                self.start_edit(EditField::Title);
+            }
+            KeyCode::Char('i') => {
+                self.start_edit(EditField::Interpret);
+            }
+            KeyCode::Char('?') => {
+                self.show_help = true;
+            }
+            KeyCode::Char('q') => {
+                self.exit = true;
+            }
+            _ => {}
+        }
+    }
+    fn play(&mut self) {
+        if !self.playing && self.transfered && self.transfer.is_none() {
+            self.playing = true;
+            self.send_command(Command::Play);
+        }
+    }
+    fn next(&mut self) {
+        if self.game_over.is_some() || self.transfer.is_some() {
+            return;
+        }
+
+        self.revealed_at = None;
+        self.send_command(Command::Pause);
+        self.playing = false;
+
+        if self.song_graded() {
+            self.notice = None;
+            self.record_grading();
+            if self.position < self.order.len() - 1 {
+                self.reset_song_state();
+                self.position += 1;
+                self.title = self.order[self.position];
+            } else {
+                self.end_game("All songs played");
+            }
+        } else {
+            let grading = &self.current_grading;
+            let notice = match (grading.title, grading.interpret) {
+                (None, None) => "Grade title and interpret first",
+                (None, _) => "Grade the title first",
+                _ => "Grade the interpret first",
+            };
+            self.notice = Some((notice, Instant::now()));
+        }
+    }
+    /// Time until the pending automatic advance, if a reveal started one
+    fn advance_in(&self) -> Option<Duration> {
+        let delay = self.auto_advance?;
+        let revealed_at = self.revealed_at?;
+        Some(delay.saturating_sub(revealed_at.elapsed()))
+    }
+    fn check_auto_advance(&mut self) {
+        if self.advance_in().is_some_and(|left| left.is_zero()) {
+            self.next();
+        }
+    }
+    fn check_duration(&mut self) {
+        let out_of_time = self.confirmed
+            && self
+                .max_duration
+                .is_some_and(|max| self.clock.elapsed() >= max);
+
+        if out_of_time && self.game_over.is_none() {
+            if self.song_graded() {
+                self.record_grading();
+            }
+            self.pause();
+            self.end_game("Time is up");
+        }
+    }
+    fn sync_play_state(&mut self) {
+        if self.no_audio {
+            return;
+        }
+        // Lets clients correct themselves if they missed a play or pause.
+        self.send_command(Command::PlayState);
+    }
+    /// Ends the game right away, the current song only counts if it was graded.
+    fn skip_to_results(&mut self) {
+        if self.game_over.is_some() {
+            return;
+        }
+
+        self.pause();
+        let first_skipped = if self.song_graded() {
+            self.record_grading();
+            self.position + 1
+        } else {
+            self.position
+        };
+        self.skipped = self.order[first_skipped..].to_vec();
+        info!("Skipping {} songs", self.skipped.len());
+        self.end_game("Skipped to results");
+    }
+    fn end_game(&mut self, reason: &'static str) {
+        info!(
+            "Game over ({}) after {} songs",
+            reason,
+            self.grading_history.len()
+        );
+        self.game_over = Some(reason);
+        self.send_summary();
+
+        if let Some(path) = &self.export_m3u {
+            match self.export_m3u(path) {
+                Ok(()) => info!("Exported playlist to {}", path.display()),
+                Err(err) => error!("Could not export playlist to {}: {}", path.display(), err),
+            }
+        }
+    }
+    /// Writes the songs played so far, in play order, as an extended M3U playlist.
+    fn export_m3u(&self, path: &Path) -> Result<(), ServerError> {
+        let mut playlist = String::from("#EXTM3U\n");
+        for &index in &self.order[..=self.position] {
+            let Some(song) = self.song_path_for(index) else {
+                continue;
+            };
+            let title = &self.titles.titles[index];
+            playlist.push_str(&format!(
+                "#EXTINF:-1,{} - {}\n{}\n",
+                title.interpret,
+                title.title,
+                song.display()
+            ));
+        }
+        fs::write(path, playlist)?;
+        Ok(())
+    }
+    fn seek(&mut self, command: Command) {
+        if !self.transfered || self.no_audio {
+            return;
+        }
+        self.send_command(command);
+    }
+    fn chime(&mut self) {
+        self.send_command(Command::Chime);
+    }
+    fn reveal_clue(&mut self) {
+        if self.clues_revealed >= self.titles.titles[self.title].clues.len() {
+            return;
+        }
+        self.clues_revealed += 1;
+        self.send_command(Command::Hint);
+    }
+    fn resync(&mut self) {
+        self.send_command(Command::Resync);
+        // The resync clears any reveal on the clients, so restore a current one.
+        if self.revealed {
+            self.reveal();
+        }
+    }
+    fn show_answer(&mut self) {
+        self.send_command(Command::ShowAnswer);
+    }
+    fn reveal(&mut self) {
+        if self.game_over.is_none() {
+            self.revealed_at = Some(Instant::now());
+        }
+        self.revealed = true;
+        self.send_command(Command::Reveal);
+    }
+    fn toggle_shuffle(&mut self) {
+        self.shuffle = !self.shuffle;
+
+        // Only the songs after the current one are reordered, so anything
+        // already played can never come up again.
+        let upcoming = &mut self.order[self.position + 1..];
+        if self.shuffle {
+            upcoming.shuffle(&mut rand::thread_rng());
+        } else {
+            upcoming.sort_unstable();
+        }
+    }
+    fn repeat(&mut self) {
+        if self.max_replays.is_some_and(|max| self.replays_used >= max) {
+            info!("No replays left for song {}", self.position + 1);
+            return;
+        }
+        if self.transfered {
+            self.replays_used += 1;
+            self.playing = false;
+            self.send_command(Command::Repeat);
+        }
+    }
+    fn pause(&mut self) {
+        if self.playing && self.transfered {
+            self.playing = false;
+            self.send_command(Command::Pause);
+        }
+    }
+    fn reset_grading(&mut self) {
+        self.current_grading = Grading::default();
+    }
+    /// Per-player rounds may leave the room grading open, the song still counts.
+    fn song_graded(&self) -> bool {
+        self.is_warmup()
+            || self.grading_mode == GradingMode::Player
+            || self.current_grading.is_complete()
+            || self.current_grading.disputed
+    }
+    fn record_grading(&mut self) {
+        self.grading_history.push(self.current_grading.clone());
+        self.graded_at.push(unix_time());
+        if self.is_warmup() {
+            // Kept in the history so it stays aligned with the play order.
+            self.player_gradings.clear();
+            self.player_grading_history.push(HashMap::new());
+            self.reset_grading();
+            return;
+        }
+        self.audit(self.grading_history.len(), false);
+        for (nickname, grading) in &self.player_gradings {
+            let points = self.round_points(grading);
+            *self.player_points.entry(nickname.clone()).or_default() += points;
+        }
+        self.player_grading_history.push(std::mem::take(&mut self.player_gradings));
+        self.reset_grading();
+    }
+    /// Takes back the last recorded grading and returns to its song, graded as it was.
+    /// Only while the game runs, clients have been shown their results once it is over.
+    fn undo_grading(&mut self) {
+        if self.game_over.is_some() || self.transfer.is_some() {
+            return;
+        }
+        let Some(grading) = self.grading_history.pop() else {
+            return;
+        };
+        self.graded_at.pop();
+        let players = self.player_grading_history.pop().unwrap_or_default();
+
+        self.pause();
+        self.revealed_at = None;
+        self.reset_song_state();
+        self.position = self.position.saturating_sub(1);
+        self.title = self.order[self.position];
+
+        for (nickname, grading) in &players {
+            let points = self.round_points(grading);
+            if let Some(total) = self.player_points.get_mut(nickname) {
+                *total = total.saturating_sub(points);
+            }
+        }
+        self.current_grading = grading;
+        self.player_gradings = players;
+        if !self.is_warmup() {
+            self.audit(self.grading_history.len() + 1, true);
+        }
+        info!("Undid the grading of song {}", self.position + 1);
+    }
+    /// Everything tied to the current song, cleared whenever another one comes up.
+    fn reset_song_state(&mut self) {
+        self.transfered = false;
+        self.clues_revealed = 0;
+        self.revealed = false;
+        self.buzzes.clear();
+        self.guesses.clear();
+        self.pending_grades.clear();
+        self.song_generation += 1;
+        self.replays_used = 0;
+        self.countdown_generation += 1;
+        self.countdown = None;
+    }
+    /// Starts counting down from the configured length, replacing a running countdown.
+    fn start_countdown(&mut self) {
+        if self.game_over.is_some() {
+            return;
+        }
+        self.countdown_generation += 1;
+        self.countdown = Some(self.countdown_secs);
+
+        let generation = self.countdown_generation;
+        let secs = self.countdown_secs;
+        let sender = self.event_sender.clone();
+        thread::spawn(move || {
+            for left in (0..=secs).rev() {
+                let tick = AppEvent::Countdown { generation, left };
+                if sender.send(tick).is_err() {
+                    return;
+                }
+                if left > 0 {
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        });
+    }
+    fn countdown_tick(&mut self, generation: u64, left: u64) {
+        if generation != self.countdown_generation {
+            return;
+        }
+        self.countdown = Some(left);
+        self.send_command(Command::Countdown);
+        if left == 0 {
+            // Clients pause on their own, this keeps the play state in line with them.
+            self.pause();
+            self.countdown = None;
+        }
+    }
+    /// Plays the song with this number next, by swapping it with the current one in the
+    /// play order. Songs already played stay graded, `u` takes them back instead.
+    fn jump_to(&mut self, number: usize) {
+        if self.game_over.is_some() || self.transfer.is_some() {
+            return;
+        }
+        let Some(index) = number
+            .checked_sub(1)
+            .filter(|index| *index < self.titles.titles.len())
+        else {
+            warn!("There is no song {}", number);
+            return;
         };
+        let Some(slot) = self.order.iter().position(|&title| title == index) else {
+            return;
+        };
+        if slot < self.position {
+            warn!(
+                "Song {} was already played, undo its grading to redo it",
+                number
+            );
+            return;
+        }
 
-        frame.render_widget(song_info, outer_layout[0]);
-        frame.render_widget(connection_info, inner_layout[0]);
-        frame.render_widget(game_info, inner_layout[1]);
+        self.pause();
+        self.revealed_at = None;
+        self.reset_song_state();
+        self.reset_grading();
+        self.player_gradings.clear();
+        self.order.swap(self.position, slot);
+        self.title = self.order[self.position];
+        info!("Jumped to song {}", number);
+    }
+    fn match_jump_key(&mut self, code: KeyCode) {
+        let Some(jump) = self.jump.as_mut() else {
+            return;
+        };
+        match code {
+            KeyCode::Char(digit) if digit.is_ascii_digit() => jump.push(digit),
+            KeyCode::Backspace => {
+                jump.pop();
+            }
+            KeyCode::Enter => {
+                if let Some(number) = self.jump.take().and_then(|jump| jump.parse().ok()) {
+                    self.jump_to(number);
+                }
+            }
+            KeyCode::Esc => self.jump = None,
+            _ => {}
+        }
+    }
+    fn match_history_key(&mut self, code: KeyCode) {
+        let Some(selected) = self.history else {
+            return;
+        };
+        let last = self.grading_history.len().saturating_sub(1);
+        self.history = match code {
+            KeyCode::Up => Some(selected.saturating_sub(1)),
+            KeyCode::Down => Some((selected + 1).min(last)),
+            KeyCode::PageUp => Some(selected.saturating_sub(HISTORY_PAGE)),
+            KeyCode::PageDown => Some((selected + HISTORY_PAGE).min(last)),
+            KeyCode::Home => Some(0),
+            KeyCode::End => Some(last),
+            KeyCode::Char('l') | KeyCode::Esc => None,
+            _ => Some(selected),
+        };
+    }
+    /// Opens the correction prompt filled with the current text.
+    fn start_edit(&mut self, field: EditField) {
+        let current = &self.titles.titles[self.title];
+        let text = match field {
+            EditField::Title => current.title.clone(),
+            EditField::Interpret => current.interpret.clone(),
+        };
+        self.edit = Some((field, text));
+    }
+    fn match_edit_key(&mut self, code: KeyCode) {
+        let Some((_, text)) = self.edit.as_mut() else {
+            return;
+        };
+        match code {
+            KeyCode::Char(char) => text.push(char),
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Enter => {
+                if let Some((field, text)) = self.edit.take() {
+                    self.apply_edit(field, text);
+                }
+            }
+            KeyCode::Esc => self.edit = None,
+            _ => {}
+        }
+    }
+    /// Corrects the current song in memory, so later reveals show the fix, and in the
+    /// titles file if asked to. A blank correction is dropped.
+    fn apply_edit(&mut self, field: EditField, text: String) {
+        let text = text.trim().to_string();
+        if text.is_empty() {
+            return;
+        }
+
+        let current = &mut self.titles.titles[self.title];
+        let target = match field {
+            EditField::Title => &mut current.title,
+            EditField::Interpret => &mut current.interpret,
+        };
+        let song = self.title + 1;
+        info!("Corrected song {} from {:?} to {:?}", song, target, text);
+        *target = text;
+
+        if let Some(path) = &self.titles_path {
+            let saved = serde_json::to_string_pretty(&self.titles)
+                .map_err(ServerError::from)
+                .and_then(|json| Ok(fs::write(path, json)?));
+            if let Err(err) = saved {
+                error!("Could not save the edit to {}: {}", path.display(), err);
+            }
+        }
+    }
+    fn round_points(&self, grading: &Grading) -> u32 {
+        self.scoring.points(
+            grading.title.is_some_and(|val| val) as usize,
+            grading.interpret.is_some_and(|val| val) as usize,
+        )
+    }
+    fn audit(&self, song: usize, undone: bool) {
+        if let Some(path) = &self.audit_log {
+            if let Err(err) = self.write_audit_entry(path, song, undone) {
+                error!("Could not write to audit log {}: {}", path.display(), err);
+            }
+        }
+    }
+    fn write_audit_entry(&self, path: &Path, song: usize, undone: bool) -> Result<(), ServerError> {
+        let title = &self.titles.titles[self.title];
+        let entry = AuditEntry {
+            song,
+            title: &title.title,
+            interpret: &title.interpret,
+            title_correct: self.current_grading.title,
+            interpret_correct: self.current_grading.interpret,
+            disputed: self.current_grading.disputed,
+            undone,
+            timestamp: unix_time(),
+        };
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+    fn toggle_grading_mode(&mut self) {
+        self.grading_mode = match self.grading_mode {
+            GradingMode::Room => GradingMode::Player,
+            GradingMode::Player => GradingMode::Room,
+        };
+        info!("Switched to {:?} grading", self.grading_mode);
+    }
+    /// The grading the keys currently act on, none in per-player mode without a selection.
+    fn active_grading(&mut self) -> Option<&mut Grading> {
+        match self.grading_mode {
+            GradingMode::Room => Some(&mut self.current_grading),
+            GradingMode::Player => {
+                let nickname = self.selected_nickname()?;
+                Some(self.player_gradings.entry(nickname).or_default())
+            }
+        }
+    }
+    fn grade_title(&mut self, grade: bool) {
+        if let Some(grading) = self.active_grading() {
+            grading.title = Some(grade);
+        }
+    }
+    fn grade_interpret(&mut self, grade: bool) {
+        if let Some(grading) = self.active_grading() {
+            grading.interpret = Some(grade);
+        }
+    }
+    /// Sends the current song to every client again, for when the audio glitched
+    /// room-wide. Playback resumes afterwards if the song was playing.
+    fn replay_for_everyone(&mut self) {
+        if self.transfer.is_some() {
+            return;
+        }
 
-        let nicknames: Vec<String> = self
+        let was_playing = self.playing;
+        self.pause();
+        info!("Replaying the current song for everyone");
+        self.play_after_transfer = was_playing;
+        self.transfer_file();
+    }
+    /// Streams the current song from a separate thread so the host screen keeps
+    /// updating, progress and failures come back through the event channel.
+    fn transfer_file(&mut self) {
+        self.cancel_preload();
+        if self.preloaded.take() == Some(self.title) {
+            // Clients that missed the preload ask for the song with a resend.
+            info!("Switching clients to the preloaded song {}", self.title + 1);
+            self.send_command(Command::SwapPreloaded);
+            self.start_preload();
+            return;
+        }
+
+        let Some(path) = self.song_path() else {
+            error!("No song file found for song {}", self.title + 1);
+            self.transfered = false;
+            return;
+        };
+        let song_size = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) => {
+                error!("Could not transfer {}: {}", path.display(), err);
+                self.transfered = false;
+                return;
+            }
+        };
+
+        let hash = self.song_hashes.lock().unwrap().get(&path).copied();
+        let targets: Vec<Writer> = self
+            .handles
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter_map(|client| {
+                if keeps_song(client, &path, hash) {
+                    return None;
+                }
+                let stream = client.stream.try_clone().ok()?;
+                Some((client.id, stream, client.write_lock.clone()))
+            })
+            .collect();
+        self.spawn_transfer(path, song_size, targets);
+    }
+    /// Streams the song to the targets on worker threads, progress and the clients it
+    /// failed for come back as events.
+    fn spawn_transfer(&mut self, path: PathBuf, song_size: u64, targets: Vec<Writer>) {
+        let total = song_size * targets.len() as u64;
+        self.transfer = Some((0, total));
+
+        let workers = self.transfer_threads.min(targets.len()).max(1);
+        let sender = self.event_sender.clone();
+        thread::spawn(move || {
+            let targets = Mutex::new(targets.into_iter());
+            let done = AtomicU64::new(0);
+            let failed = Mutex::new(Vec::new());
+
+            // Each worker takes the next client until none are left.
+            thread::scope(|scope| {
+                for _ in 0..workers {
+                    scope.spawn(|| loop {
+                        let Some((id, mut stream, write_lock)) = targets.lock().unwrap().next()
+                        else {
+                            break;
+                        };
+
+                        // Waits for a resend to the client to finish first.
+                        let _writing = write_lock.lock().unwrap();
+                        let mut reported = 0;
+                        let result = stream_file(&mut stream, &path, |sent| {
+                            let step = sent - reported;
+                            reported = sent;
+                            let done = done.fetch_add(step, Ordering::Relaxed) + step;
+                            let _ = sender.send(AppEvent::TransferProgress(done, total));
+                        });
+                        if let Err(err) = result {
+                            warn!("Transfer to client {} failed: {}", id, err);
+                            // Count the rest as done so the total still adds up.
+                            done.fetch_add(song_size.saturating_sub(reported), Ordering::Relaxed);
+                            failed.lock().unwrap().push(id);
+                        }
+                    });
+                }
+            });
+
+            let failed = failed.into_inner().unwrap();
+            let _ = sender.send(AppEvent::TransferFinished(failed));
+        });
+    }
+    /// Drops the clients the transfer failed for, then sends what was held back meanwhile.
+    fn finish_transfer(&mut self, failed: Vec<usize>) {
+        self.transfer = None;
+        for id in failed {
+            self.remove_client(id);
+        }
+
+        let mut clients = self.handles.lock().unwrap();
+        for deferred in self.deferred.drain(..) {
+            broadcast_deferred(&mut clients, &deferred);
+        }
+        if let Some(playing) = self.deferred_play_state.take() {
+            let payload = [playing as u8];
+            broadcast(&mut clients, &Command::PlayState, |_| Some(&payload));
+        }
+    }
+    /// Sends right away, or once the running transfer is done.
+    fn deliver(&mut self, deferred: Deferred) {
+        // Anything written now would land in the middle of the song data.
+        if self.transfer.is_some() {
+            self.deferred.push(deferred);
+        } else {
+            broadcast_deferred(&mut self.handles.lock().unwrap(), &deferred);
+        }
+    }
+    /// Sends the song after the current one in the background to everyone connected now,
+    /// one chunk at a time so commands can still go out in between.
+    fn start_preload(&mut self) {
+        self.cancel_preload();
+        if !self.preload || self.no_audio {
+            return;
+        }
+        let Some(&next) = self.order.get(self.position + 1) else {
+            return;
+        };
+        let Some(path) = self.song_path_for(next) else {
+            return;
+        };
+
+        let targets: Vec<_> = self
             .handles
             .lock()
             .unwrap()
             .iter()
-            .map(|client| client.nickname.clone())
+            .filter(|client| !client.receiving)
+            .filter_map(|client| {
+                let stream = client.stream.try_clone().ok()?;
+                Some((stream, client.write_lock.clone()))
+            })
             .collect();
 
-        List::new(nicknames)
-            .block(title_block("Clients"))
-            .render(inner_layout[2], frame.buffer_mut());
+        let cancel = Arc::new(AtomicBool::new(false));
+        let stop = cancel.clone();
+        let sender = self.event_sender.clone();
+        let worker = thread::spawn(move || match preload_file(targets, &path, &stop) {
+            Ok(true) => {
+                let _ = sender.send(AppEvent::PreloadFinished(next));
+            }
+            Ok(false) => {}
+            Err(err) => warn!("Could not preload {}: {}", path.display(), err),
+        });
+        self.preload_worker = Some((cancel, worker));
     }
-    fn handle_events(&mut self) -> Result<(), Box<dyn Error>> {
-        match self.event_channel.recv()? {
-            AppEvent::ClientUpdate => {}
-            AppEvent::CrossTerm(event) => match event {
-                Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
-                    self.match_key_event(key_event);
+    fn cancel_preload(&mut self) {
+        if let Some((cancel, worker)) = self.preload_worker.take() {
+            // The worker stops after the chunk it is writing, a transfer must not start
+            // before that one is complete.
+            cancel.store(true, Ordering::Relaxed);
+            let _ = worker.join();
+        }
+    }
+    fn is_player(&self, id: usize) -> bool {
+        self.handles
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|client| client.id == id && !client.mirror)
+    }
+    fn buzz(&mut self, id: usize, nickname: String, at: Instant) {
+        if !self.is_player(id) || self.buzzes.iter().any(|(buzzed, _)| *buzzed == nickname) {
+            return;
+        }
+
+        info!("{} buzzed", nickname);
+        // Readers run in parallel, so order by arrival rather than by the channel.
+        let index = self.buzzes.partition_point(|(_, earlier)| *earlier <= at);
+        self.buzzes.insert(index, (nickname, at));
+    }
+    fn guess(&mut self, id: usize, nickname: String, guess: String) {
+        if !self.is_player(id) || self.revealed {
+            return;
+        }
+
+        info!("{} guessed {:?}", nickname, guess);
+        // One guess per player is graded at a time, later ones are only kept.
+        if let Some(grader) = self.grader.clone() {
+            if self.pending_grades.insert(nickname.clone()) {
+                self.run_grader(grader, nickname.clone(), guess.clone());
+            } else {
+                warn!("Still grading the last guess of {}", nickname);
+            }
+        }
+        self.guesses.insert(nickname, guess);
+    }
+    /// Runs the grader off the main loop, its verdict comes back as an event.
+    fn run_grader(&self, grader: String, nickname: String, guess: String) {
+        let title = self.titles.titles[self.title].clone();
+        let generation = self.song_generation;
+        let sender = self.event_sender.clone();
+
+        thread::spawn(move || {
+            let mut command = process::Command::new(&grader);
+            command.arg(&guess).arg(&title.title).arg(&title.interpret);
+            let grading = match run_with_timeout(command, GRADER_TIMEOUT) {
+                Ok((success, stdout)) => Some(grader_verdict(success, &stdout)),
+                Err(err) => {
+                    error!("Could not run grader {}: {}", grader, err);
+                    None
                 }
-                _ => {}
-            },
+            };
+            let _ = sender.send(AppEvent::AutoGraded {
+                nickname,
+                generation,
+                grading,
+            });
+        });
+    }
+    fn touch_client(&mut self, id: usize) {
+        if let Some(client) = self.handles.lock().unwrap().iter_mut().find(|c| c.id == id) {
+            client.last_activity = Instant::now();
         }
-        Ok(())
     }
-    fn match_key_event(&mut self, event: KeyEvent) {
-        match event.code {
-            KeyCode::Char('o') => {
-                self.pause();
+    fn reap_idle_clients(&mut self) {
+        let Some(timeout) = self.idle_timeout else {
+            return;
+        };
+
+        self.handles.lock().unwrap().retain(|client| {
+            let idle = client.last_activity.elapsed() >= timeout;
+            if idle {
+                info!("Disconnecting idle client {}", client.nickname);
+                let _ = client.stream.shutdown(Shutdown::Both);
             }
-            KeyCode::Char('p') => {
-                self.play();
+            !idle
+        });
+    }
+    fn remove_client(&mut self, id: usize) {
+        for list in [&self.handles, &self.waiting] {
+            let mut list = list.lock().unwrap();
+            if let Some(index) = list.iter().position(|client| client.id == id) {
+                let client = list.remove(index);
+                info!("Client {} left", client.nickname);
+            }
+        }
+    }
+    /// Moves queued clients in as slots free up and tells the rest where they stand.
+    fn admit_waiting(&mut self) {
+        let mut waiting = self.waiting.lock().unwrap();
+        if waiting.is_empty() {
+            return;
+        }
+
+        let mut handles = self.handles.lock().unwrap();
+        let free = self
+            .max_clients
+            .map_or(waiting.len(), |max| max.saturating_sub(handles.len()))
+            .min(waiting.len());
+
+        for mut client in waiting.drain(..free) {
+            if send_queue_position(&mut client.stream, 0).is_ok() {
+                info!("Admitted {} from the queue", client.nickname);
+                client.last_activity = Instant::now();
+                handles.push(client);
             }
-            KeyCode::Char('t') => {
-                if !self.transfered {
-                    self.transfered = true;
-                    self.transfer_file();
+        }
+
+        if free > 0 {
+            let mut position = 1;
+            waiting.retain_mut(|client| {
+                let keep = send_queue_position(&mut client.stream, position).is_ok();
+                position += keep as u64;
+                keep
+            });
+        }
+    }
+    fn resend(&mut self, id: usize) {
+        if !self.transfered || self.transfer.is_some() {
+            return;
+        }
+
+        let Some(path) = self.song_path() else {
+            return;
+        };
+        if let Err(err) = fs::metadata(&path) {
+            error!("Could not resend {}: {}", path.display(), err);
+            return;
+        }
+
+        let hash = self.song_hashes.lock().unwrap().get(&path).copied();
+        let target = self
+            .handles
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|client| client.id == id && !client.receiving)
+            .and_then(|client| {
+                if keeps_song(client, &path, hash) {
+                    return None;
                 }
+                let stream = client.stream.try_clone().ok()?;
+                client.receiving = true;
+                Some((stream, client.write_lock.clone()))
+            });
+        let Some((mut stream, write_lock)) = target else {
+            return;
+        };
+
+        // Preload chunks must not land in the middle of the song.
+        if self.preloaded.is_none() {
+            self.cancel_preload();
+        }
+        // Only this client waits for the song, the rest of the room plays on meanwhile.
+        info!("Resending song {} to client {}", self.title + 1, id);
+        let sender = self.event_sender.clone();
+        thread::spawn(move || {
+            let _writing = write_lock.lock().unwrap();
+            let result = stream_file(&mut stream, &path, |_| {});
+            if let Err(err) = &result {
+                warn!("Resend to client {} failed: {}", id, err);
             }
-            KeyCode::Char('a') => {
-                self.grade_title(false);
+            let _ = sender.send(AppEvent::ResendFinished(id, result.is_ok()));
+        });
+    }
+    /// Brings a client that got its song again up to date with what it missed meanwhile.
+    fn finish_resend(&mut self, id: usize, sent: bool) {
+        if !sent {
+            self.remove_client(id);
+        } else {
+            let resync = serde_json::to_string(&self.resync_state());
+            let mut clients = self.handles.lock().unwrap();
+            if let Some(client) = clients.iter_mut().find(|client| client.id == id) {
+                client.receiving = false;
             }
-            KeyCode::Char('s') => {
-                self.grade_title(true);
+            match resync {
+                Ok(json) => {
+                    let payload = string_payload(&json);
+                    broadcast(&mut clients, &Command::Resync, |client| {
+                        (client.id == id).then_some(payload.as_slice())
+                    });
+                }
+                Err(err) => error!("Could not resync client {}: {}", id, err),
             }
-            KeyCode::Char('y') => {
-                self.grade_interpret(false);
+        }
+        if self.preloaded.is_none() && self.transfer.is_none() {
+            self.start_preload();
+        }
+    }
+    fn resync_state(&self) -> Resync {
+        Resync {
+            song: self.position + 1,
+            total: self.titles.titles.len(),
+            playing: self.playing,
+            transfered: self.transfered,
+        }
+    }
+    fn host_state(&self) -> HostState {
+        let (titles_correct, interprets_correct) = self.tally();
+        let current = &self.titles.titles[self.title];
+
+        HostState {
+            song: self.position + 1,
+            total: self.titles.titles.len(),
+            titles_correct,
+            interprets_correct,
+            disputed: self.disputed_songs().len(),
+            points: self.scoring.points(titles_correct, interprets_correct),
+            elapsed_secs: self.clock.elapsed().as_secs(),
+            time_left_secs: self
+                .max_duration
+                .map(|max| max.saturating_sub(self.clock.elapsed()).as_secs()),
+            answer: self
+                .revealed
+                .then(|| (current.title.clone(), current.interpret.clone())),
+            players: self
+                .handles
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|client| !client.mirror)
+                .map(|client| client.nickname.clone())
+                .collect(),
+            game_over: self.game_over.map(str::to_string),
+        }
+    }
+    /// Refreshes the JSON the status endpoint serves, if it runs.
+    fn publish_status(&self) {
+        let Some(status) = &self.status else {
+            return;
+        };
+        let snapshot = Status {
+            game: self.host_state(),
+            scores: self.player_points.clone(),
+        };
+        match serde_json::to_string(&snapshot) {
+            Ok(json) => *status.lock().unwrap() = json,
+            Err(err) => error!("Could not serialize the status: {}", err),
+        }
+    }
+    fn send_summary(&mut self) {
+        let (titles_correct, interprets_correct) = self.tally();
+        let room_points = self.scoring.points(titles_correct, interprets_correct);
+        let songs = self.scored_history().count();
+        let possible = self.scoring.points(songs, songs);
+
+        // Mirrors only watch, they get no summary of their own.
+        let player_payloads = self
+            .handles
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|client| !client.mirror)
+            .map(|client| {
+                let points = if self.player_points.is_empty() {
+                    room_points
+                } else {
+                    self.player_points.get(&client.nickname).copied().unwrap_or(0)
+                };
+                let summary = Summary {
+                    points,
+                    possible,
+                    songs,
+                };
+                serde_json::to_string(&summary)
+                    .map(|json| (client.nickname.clone(), string_payload(&json)))
+            })
+            .collect::<Result<HashMap<_, _>, _>>();
+        match player_payloads {
+            // Goes out after a running transfer, so a game ending meanwhile still has one.
+            Ok(player_payloads) => self.deliver(Deferred {
+                command: Command::Summary,
+                payload: None,
+                player_payloads,
+            }),
+            Err(err) => error!("Could not serialize summary: {}", err),
+        }
+    }
+    fn broadcast_host_state(&mut self) {
+        if !self.confirmed || self.transfer.is_some() {
+            return;
+        }
+
+        let json = match serde_json::to_string(&self.host_state()) {
+            Ok(json) => json,
+            Err(err) => {
+                error!("Could not serialize host state: {}", err);
+                return;
             }
-            KeyCode::Char('x') => {
-                self.grade_interpret(true);
+        };
+        let payload = string_payload(&json);
+
+        self.handles.lock().unwrap().retain_mut(|client| {
+            if !client.mirror || client.receiving {
+                return true;
             }
-            KeyCode::Char('n') => {
-                self.next().unwrap();
+
+            let _writing = client.write_lock.lock().unwrap();
+            write_framed_message(&mut client.stream, &Command::HostState, &payload).is_ok()
+        });
+    }
+    fn song_path(&self) -> Option<PathBuf> {
+        self.song_path_for(self.title)
+    }
+    /// The first existing song file for the title, trying the extensions in order
+    fn song_path_for(&self, index: usize) -> Option<PathBuf> {
+        self.extensions
+            .iter()
+            .map(|extension| self.music_dir.join(format!("{}.{}", index + 1, extension)))
+            .find(|path| path.is_file())
+    }
+    fn send_command(&mut self, command: Command) {
+        let numeric = command.code();
+        let playing = self.playing;
+
+        let current = &self.titles.titles[self.title];
+        let clue = self
+            .clues_revealed
+            .checked_sub(1)
+            .and_then(|index| current.clues.get(index))
+            .cloned()
+            .unwrap_or_default();
+        let title_grading = |grading: &Grading| TitleGrading {
+            title: current.title.clone(),
+            interpret: current.interpret.clone(),
+            title_correct: grading.title,
+            interpret_correct: grading.interpret,
+            title_points: self.scoring.title_points,
+            interpret_points: self.scoring.interpret_points,
+            reveal_delay: current.reveal_delay,
+        };
+
+        let resync = self.resync_state();
+
+        let answer = Answer {
+            title: current.title.clone(),
+            interpret: current.interpret.clone(),
+        };
+
+        // Serialize once up front: a failure here is a server-side problem and
+        // must not count against the clients below.
+        let json = match command {
+            Command::Reveal => Some(serde_json::to_string(&title_grading(&self.current_grading))),
+            Command::Resync => Some(serde_json::to_string(&resync)),
+            Command::ShowAnswer => Some(serde_json::to_string(&answer)),
+            _ => None,
+        };
+        let json = match json.transpose() {
+            Ok(json) => json,
+            Err(err) => {
+                error!("Skipping command {}, could not serialize it: {}", numeric, err);
+                return;
             }
-            KeyCode::Char('r') => {
-                self.repeat();
+        };
+
+        // In per-player mode every player is revealed their own grading.
+        let player_reveals = if matches!(command, Command::Reveal)
+            && self.grading_mode == GradingMode::Player
+        {
+            self.handles
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|client| {
+                    let grading = self.player_gradings.get(&client.nickname).cloned();
+                    serde_json::to_string(&title_grading(&grading.unwrap_or_default()))
+                        .map(|json| (client.nickname.clone(), string_payload(&json)))
+                })
+                .collect::<Result<HashMap<_, _>, _>>()
+        } else {
+            Ok(HashMap::new())
+        };
+        let player_reveals = match player_reveals {
+            Ok(player_reveals) => player_reveals,
+            Err(err) => {
+                error!("Skipping reveal, could not serialize a player grading: {}", err);
+                return;
             }
-            KeyCode::Char('q') => {
-                self.exit = true;
+        };
+
+        let payload = match (&command, json) {
+            (_, Some(json)) => string_payload(&json),
+            (Command::Hint, None) => string_payload(&clue),
+            (Command::PlayState, None) => vec![playing as u8],
+            (Command::Play, None) => {
+                // The start time, then where the quiz stands for the players' orientation.
+                let start_at = unix_millis() + PLAY_LEAD.as_millis() as u64;
+                let mut payload = start_at.to_be_bytes().to_vec();
+                payload.extend_from_slice(&(self.position as u64 + 1).to_be_bytes());
+                payload.extend_from_slice(&(self.titles.titles.len() as u64).to_be_bytes());
+                payload
             }
-            _ => {}
+            (Command::Countdown, None) => {
+                let mut payload = self.countdown.unwrap_or(0).to_be_bytes().to_vec();
+                payload.extend_from_slice(&self.countdown_secs.to_be_bytes());
+                payload
+            }
+            (Command::SeekForward | Command::SeekBack, None) => {
+                SEEK_STEP.as_secs().to_be_bytes().to_vec()
+            }
+            _ => Vec::new(),
+        };
+
+        if self.transfer.is_some() && matches!(command, Command::PlayState) {
+            self.deferred_play_state = Some(playing);
+            return;
         }
+        self.deliver(Deferred {
+            command,
+            payload: Some(payload),
+            player_payloads: player_reveals,
+        });
     }
-    fn play(&mut self) {
-        if !self.playing && self.transfered {
-            self.playing = true;
-            match self.send_command(Command::Play) {
-                Ok(_) => {}
-                Err(_) => {
-                    self.exit = true;
-                }
-            }
+}
+
+/// Writes to every client at once so a slow one only holds up itself, then drops the
+/// clients the write failed for. Clients without a payload are left out.
+fn broadcast<'a>(
+    clients: &mut Vec<Client>,
+    command: &Command,
+    payload_for: impl Fn(&Client) -> Option<&'a [u8]>,
+) {
+    let failed: Vec<usize> = thread::scope(|scope| {
+        let writes: Vec<_> = clients
+            .iter_mut()
+            .filter(|client| !client.receiving)
+            .filter_map(|client| {
+                let payload = payload_for(client)?;
+                Some(scope.spawn(move || {
+                    let _writing = client.write_lock.lock().unwrap();
+                    write_framed_message(&mut client.stream, command, payload)
+                        .map_err(|_| client.id)
+                }))
+            })
+            .collect();
+        writes
+            .into_iter()
+            .filter_map(|write| write.join().ok()?.err())
+            .collect()
+    });
+    clients.retain(|client| !failed.contains(&client.id));
+}
+
+/// Sends every client its own payload of the command, or else the shared one.
+fn broadcast_deferred(clients: &mut Vec<Client>, deferred: &Deferred) {
+    broadcast(clients, &deferred.command, |client| {
+        let own = deferred.player_payloads.get(&client.nickname);
+        own.or(deferred.payload.as_ref()).map(Vec::as_slice)
+    });
+}
+
+/// Answers every HTTP request with the latest status JSON, whatever the path. Requests
+/// are handled one at a time, a scoreboard polling now and then needs no more.
+fn serve_status(listener: TcpListener, status: Arc<Mutex<String>>) {
+    for mut stream in listener.incoming().flatten() {
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(2)));
+        // Only the start of the request is read, it is answered the same either way.
+        let _ = stream.read(&mut [0_u8; 1024]);
+
+        let body = status.lock().unwrap().clone();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        if let Err(err) = stream.write_all(response.as_bytes()) {
+            warn!("Could not answer a status request: {}", err);
         }
     }
-    fn next(&mut self) -> Result<(), Box<dyn Error>> {
-        self.send_command(Command::Pause)?;
-        self.playing = false;
+}
+
+fn read_handshake(stream: &mut TcpStream) -> io::Result<Handshake> {
+    let mut bytes_to_read = [0_u8; 64 / 8];
+    stream.read_exact(&mut bytes_to_read)?;
+
+    let length_numeric = u64::from_be_bytes(bytes_to_read);
+    if length_numeric > MAX_HANDSHAKE_LENGTH {
+        return Err(invalid_handshake(format!("{} bytes long", length_numeric)));
+    }
+    let mut buffer = vec![0_u8; length_numeric as usize];
+
+    stream.read_exact(&mut buffer)?;
+
+    let raw = String::from_utf8(buffer).map_err(io::Error::other)?;
+    let handshake = serde_json::from_str(&raw).unwrap_or(Handshake {
+        nickname: raw,
+        color: None,
+        mirror: false,
+        song_hash: None,
+    });
+
+    if handshake.nickname.trim().is_empty() {
+        return Err(invalid_handshake("blank nickname".to_string()));
+    }
+    if handshake.nickname.len() > MAX_NICKNAME_LENGTH {
+        return Err(invalid_handshake(format!(
+            "nickname of {} bytes",
+            handshake.nickname.len()
+        )));
+    }
+    Ok(handshake)
+}
 
-        if self.current_grading.title.is_some() && self.current_grading.interpret.is_some() {
-            self.grading_history.push(self.current_grading.clone());
-            self.reset_grading();
-            if (self.title as usize) < self.titles.titles.len() - 1 {
-                self.transfered = false;
-                self.title += 1;
-            }
-        }
+fn invalid_handshake(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
 
-        Ok(())
-    }
-    fn repeat(&mut self) {
-        if self.transfered {
-            self.playing = false;
-            match self.send_command(Command::Repeat) {
-                Ok(_) => {}
-                Err(_) => {
-                    self.exit = true;
+/// A length-prefixed string as it appears inside a message.
+fn string_payload(value: &str) -> Vec<u8> {
+    let mut payload = (value.len() as u64).to_be_bytes().to_vec();
+    payload.extend_from_slice(value.as_bytes());
+    payload
+}
+
+/// Starts a message: the magic, the length of the rest (command code plus
+/// `payload_length` bytes) and the command code.
+fn write_frame_header(
+    stream: &mut TcpStream,
+    command: &Command,
+    payload_length: u64,
+) -> io::Result<()> {
+    let mut header = FRAME_MAGIC.to_vec();
+    header.extend_from_slice(&(1 + payload_length).to_be_bytes());
+    header.push(command.code());
+    stream.write_all(&header)
+}
+
+fn write_framed_message(
+    stream: &mut TcpStream,
+    command: &Command,
+    payload: &[u8],
+) -> io::Result<()> {
+    write_frame_header(stream, command, payload.len() as u64)?;
+    stream.write_all(payload)
+}
+
+/// Tells a client its place in the queue, 0 means it has been let in.
+fn send_queue_position(stream: &mut TcpStream, position: u64) -> io::Result<()> {
+    write_framed_message(stream, &Command::Queued, &position.to_be_bytes())
+}
+
+fn send_text(stream: &mut TcpStream, command: Command, text: &str) -> io::Result<()> {
+    write_framed_message(stream, &command, &string_payload(text))
+}
+
+/// Drains everything a client sends and forwards it to the main loop, so
+/// unsolicited bytes never pile up in the socket buffer.
+fn client_reader(
+    id: usize,
+    nickname: String,
+    mut stream: TcpStream,
+    sender: mpsc::Sender<AppEvent>,
+) {
+    let mut bytes = [0_u8; 1];
+    let mut stalled = 0;
+
+    loop {
+        match stream.read_exact(&mut bytes) {
+            Ok(()) => stalled = 0,
+            // Some silence is fine, but clients send heartbeats well within the timeout.
+            Err(err)
+                if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) =>
+            {
+                stalled += 1;
+                if stalled >= MAX_STALLED_READS {
+                    warn!("Client {} stalled, no request within the read timeout", id);
+                    break;
                 }
+                continue;
             }
+            Err(_) => break,
         }
-    }
-    fn pause(&mut self) {
-        if self.playing && self.transfered {
-            self.playing = false;
-            match self.send_command(Command::Pause) {
-                Ok(_) => {}
-                Err(_) => {
-                    self.exit = true;
+        let code = u8::from_be_bytes(bytes);
+        let Some(request) = Request::from_code(code) else {
+            warn!("Client {} sent unknown request {}", id, code);
+            continue;
+        };
+
+        let leave = matches!(request, Request::Leave);
+        let event = match request {
+            Request::Buzz => AppEvent::Buzz {
+                id,
+                nickname: nickname.clone(),
+                at: Instant::now(),
+            },
+            Request::Guess => match read_guess(&mut stream) {
+                Ok(guess) => AppEvent::Guess {
+                    id,
+                    nickname: nickname.clone(),
+                    guess,
+                },
+                Err(err) => {
+                    warn!("Client {} sent a broken guess: {}", id, err);
+                    break;
                 }
-            }
-        }
-    }
-    fn reset_grading(&mut self) {
-        self.current_grading = Grading {
-            title: None,
-            interpret: None,
+            },
+            request => AppEvent::ClientRequest(id, request),
+        };
+        if sender.send(event).is_err() || leave {
+            return;
         }
     }
-    fn grade_title(&mut self, grade: bool) {
-        self.current_grading.title = Some(grade);
-    }
-    fn grade_interpret(&mut self, grade: bool) {
-        self.current_grading.interpret = Some(grade);
-    }
-    fn transfer_file(&mut self) {
-        self.send_command(Command::Transfer).unwrap();
+
+    let _ = sender.send(AppEvent::ClientLeft(id));
+}
+
+fn read_guess(stream: &mut TcpStream) -> io::Result<String> {
+    let mut length = [0_u8; 8];
+    stream.read_exact(&mut length)?;
+    let length = u64::from_be_bytes(length);
+    if length > MAX_GUESS_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("guess of {} bytes", length),
+        ));
     }
-    fn send_command(&mut self, command: Command) -> Result<(), Box<dyn Error>> {
-        let numeric: u8 = match command {
-            Command::Play => 1,
-            Command::Transfer => 2,
-            Command::Pause => 3,
-            Command::Repeat => 4,
-        };
 
-        let bytes = numeric.to_be_bytes();
+    let mut guess = vec![0; length as usize];
+    stream.read_exact(&mut guess)?;
+    String::from_utf8(guess).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
 
-        self.handles.lock().unwrap().retain_mut(|client| {
-            let mut keep = true;
-            keep &= client.stream.write_all(&bytes).is_ok();
-            if keep && numeric == 2 {
-                keep &= stream_file(
-                    &mut client.stream,
-                    format!("/Users/dominik/Projects/musicquiz/{}.mp3", self.title + 1).as_str(),
-                )
-                .is_ok();
-            }
+/// Runs a program to its end and returns whether it succeeded with what it printed, or
+/// kills it once the timeout is up.
+fn run_with_timeout(
+    mut command: process::Command,
+    timeout: Duration,
+) -> io::Result<(bool, Vec<u8>)> {
+    let mut child = command
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::null())
+        .spawn()?;
 
-            keep
-        });
+    // Read alongside, a full pipe would otherwise keep the program from finishing.
+    let reader = child.stdout.take().map(|mut stdout| {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = stdout.read_to_end(&mut output);
+            output
+        })
+    });
 
-        Ok(())
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            let message = format!("gave no verdict within {:?}", timeout);
+            return Err(io::Error::new(io::ErrorKind::TimedOut, message));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    let output = reader.and_then(|reader| reader.join().ok());
+    Ok((status.success(), output.unwrap_or_default()))
+}
+
+/// Reads the title and interpret verdicts from the grader's output, e.g. "correct incorrect"
+/// or "1 0". A grader printing nothing grades both by its exit status.
+fn grader_verdict(success: bool, stdout: &[u8]) -> Grading {
+    let stdout = String::from_utf8_lossy(stdout);
+    let mut verdicts = stdout.split_whitespace().map(|word| match word {
+        "correct" | "1" => Some(true),
+        "incorrect" | "0" => Some(false),
+        _ => None,
+    });
+
+    match verdicts.next() {
+        Some(title) => Grading {
+            title,
+            interpret: verdicts.next().flatten(),
+            disputed: false,
+        },
+        None => Grading {
+            title: Some(success),
+            interpret: Some(success),
+            disputed: false,
+        },
     }
 }
 
-fn read_nickname(stream: &mut TcpStream) -> String {
-    let mut bytes_to_read = [0_u8; 64 / 8];
-    stream.read_exact(&mut bytes_to_read).unwrap();
+/// Reads the titles file, entries are checked one by one so the first broken one can be
+/// named instead of failing somewhere later.
+fn parse_titles(content: &str) -> Result<TitleList, ServerError> {
+    #[derive(Deserialize)]
+    struct RawTitleList {
+        #[serde(default)]
+        name: Option<String>,
+        titles: Vec<serde_json::Value>,
+    }
 
-    let length_numeric = u64::from_be_bytes(bytes_to_read);
-    let mut buffer = vec![0_u8; length_numeric as usize];
+    let raw: RawTitleList = serde_json::from_str(content)?;
+    if raw.titles.is_empty() {
+        return Err(ServerError::Titles("the titles list is empty".to_string()));
+    }
 
-    stream.read_exact(&mut buffer).unwrap();
+    let mut titles = Vec::with_capacity(raw.titles.len());
+    for (index, entry) in raw.titles.into_iter().enumerate() {
+        let number = index + 1;
+        let invalid = |reason: &str| ServerError::Titles(format!("title {}: {}", number, reason));
+        let title: TitleInfo =
+            serde_json::from_value(entry).map_err(|err| invalid(&err.to_string()))?;
+        if title.title.trim().is_empty() {
+            return Err(invalid("the title is blank"));
+        }
+        if title.interpret.trim().is_empty() {
+            return Err(invalid("the interpret is blank"));
+        }
+        titles.push(title);
+    }
 
-    String::from_utf8(buffer).unwrap()
+    Ok(TitleList {
+        name: raw.name,
+        titles,
+    })
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let file_content = fs::read_to_string("/Users/dominik/Projects/musicquiz/titles.json")?;
-    let titles: TitleList = serde_json::from_str(&file_content)?;
+fn main() -> Result<(), ServerError> {
+    let args = Args::parse();
+    let profile = Profile::resolve(&args)?;
+
+    if let Err(err) = simple_logging::log_to_file("test.log", LevelFilter::Info) {
+        eprintln!("Could not open log file: {}", err);
+    }
+    let music_dir = profile
+        .music_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_MUSIC_DIR));
+    // Without audio no song file is ever opened.
+    if !profile.no_audio && !music_dir.is_dir() {
+        error!("Music directory {} does not exist", music_dir.display());
+        eprintln!("Music directory {} does not exist", music_dir.display());
+        process::exit(1);
+    }
+    let file_content = fs::read_to_string(&args.titles)?;
+    let titles = match parse_titles(&file_content) {
+        Ok(titles) => titles,
+        Err(err) => {
+            error!("{}: {}", args.titles.display(), err);
+            eprintln!("{}: {}", args.titles.display(), err);
+            process::exit(1);
+        }
+    };
+
+    // Bind before taking over the terminal so a bad address is reported plainly.
+    let listener = TcpListener::bind(profile.bind.as_deref().unwrap_or(DEFAULT_BIND))?;
+    let status_listener = match profile.status_port {
+        Some(port) => Some(TcpListener::bind((listener.local_addr()?.ip(), port))?),
+        None => None,
+    };
 
-    let mut terminal = ratatui::init();
-    let listener = TcpListener::bind("0.0.0.0:6969")?;
+    let mut terminal = match init_terminal() {
+        Ok(terminal) => terminal,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
 
     let (tx, rx) = mpsc::channel::<AppEvent>();
     let clients = Arc::new(Mutex::new(Vec::<Client>::new()));
     let acceptor = clients.clone();
+    let waiting = Arc::new(Mutex::new(Vec::<Client>::new()));
+    let queue = waiting.clone();
 
     let t1 = tx.clone();
     let t2 = tx.clone();
 
+    let motd = profile.motd;
+    let quiz_name = profile.quiz_name.or(titles.name.clone());
+    let results_quiz_name = quiz_name.clone();
+    let socket_timeout = match profile.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    let max_clients = profile.max_clients;
+    let queue_when_full = profile.queue_when_full;
+
     thread::spawn(move || {
-        for mut stream in listener.incoming().flatten() {
-            let nickname = read_nickname(&mut stream);
-            let client = Client { nickname, stream };
-            acceptor.lock().unwrap().push(client);
-            t1.send(AppEvent::ClientUpdate).unwrap();
+        for (id, mut stream) in listener.incoming().flatten().enumerate() {
+            // A client that never introduces itself must not hold up the acceptor, one that
+            // stops reading must not hold up a transfer. Clones share the timeouts.
+            let _ = stream.set_read_timeout(socket_timeout);
+            let _ = stream.set_write_timeout(socket_timeout);
+            let handshake = read_handshake(&mut stream);
+
+            let Handshake {
+                nickname,
+                color,
+                mirror,
+                song_hash,
+            } = match handshake {
+                Ok(handshake) => handshake,
+                Err(err) => {
+                    warn!("Dropping connection without a handshake: {}", err);
+                    continue;
+                }
+            };
+            let color = color.and_then(|color| color.parse::<Color>().ok());
+
+            // Points and gradings are kept by nickname, so a player coming back after a
+            // drop takes over from the stale connection and keeps them.
+            for list in [&acceptor, &queue] {
+                list.lock().unwrap().retain(|client| {
+                    if client.nickname != nickname {
+                        return true;
+                    }
+                    info!("{} reconnected, closing the old connection", nickname);
+                    let _ = client.stream.shutdown(Shutdown::Both);
+                    false
+                });
+            }
+
+            if let Some(quiz_name) = &quiz_name {
+                if let Err(err) = send_text(&mut stream, Command::QuizName, quiz_name) {
+                    error!("Could not send quiz name to {}: {}", nickname, err);
+                    continue;
+                }
+            }
+
+            if let Some(motd) = &motd {
+                if let Err(err) = send_text(&mut stream, Command::Motd, motd) {
+                    error!("Could not send welcome message to {}: {}", nickname, err);
+                    continue;
+                }
+            }
+
+            let full = {
+                let queued = queue.lock().unwrap().len();
+                max_clients.is_some_and(|max| acceptor.lock().unwrap().len() + queued >= max)
+            };
+
+            if full && !queue_when_full {
+                info!("Turning away {}, the quiz is full", nickname);
+                let _ = send_text(
+                    &mut stream,
+                    Command::Motd,
+                    "The quiz is full, please try again later",
+                );
+                continue;
+            }
+
+            let reader = stream.try_clone();
+            let reader_nickname = nickname.clone();
+
+            let mut client = Client {
+                id,
+                nickname,
+                address: stream.peer_addr().ok(),
+                song_hash,
+                stream,
+                color,
+                mirror,
+                last_activity: Instant::now(),
+                write_lock: Arc::new(Mutex::new(())),
+                receiving: false,
+            };
+
+            if full {
+                let mut queue = queue.lock().unwrap();
+                let position = queue.len() as u64 + 1;
+                if let Err(err) = send_queue_position(&mut client.stream, position) {
+                    error!("Could not queue {}: {}", client.nickname, err);
+                    continue;
+                }
+                info!("Queued {} at position {}", client.nickname, position);
+                queue.push(client);
+            } else {
+                acceptor.lock().unwrap().push(client);
+            }
+
+            match reader {
+                Ok(reader) => {
+                    let requests = t1.clone();
+                    thread::spawn(move || client_reader(id, reader_nickname, reader, requests));
+                }
+                Err(err) => error!("Could not read from client {}: {}", id, err),
+            }
+
+            if t1.send(AppEvent::ClientUpdate).is_err() {
+                break;
+            }
         }
     });
 
+    let status = status_listener.map(|status_listener| {
+        let status = Arc::new(Mutex::new(String::from("{}")));
+        let served = status.clone();
+        thread::spawn(move || serve_status(status_listener, served));
+        status
+    });
+
+    let t3 = tx.clone();
     thread::spawn(move || loop {
-        let event = event::read().unwrap();
-        t2.send(AppEvent::CrossTerm(event)).unwrap();
+        thread::sleep(Duration::from_secs(1));
+        if t3.send(AppEvent::Tick).is_err() {
+            break;
+        }
+    });
+
+    thread::spawn(move || {
+        let mut failures = 0;
+
+        loop {
+            let app_event = match event::read() {
+                Ok(event) => {
+                    if failures > 0 {
+                        info!("Terminal input recovered after {} failures", failures);
+                        failures = 0;
+                        if t2.send(AppEvent::InputRecovered).is_err() {
+                            break;
+                        }
+                    }
+                    AppEvent::CrossTerm(event)
+                }
+                Err(err) => {
+                    failures += 1;
+                    error!("Failed to read terminal event ({}): {}", failures, err);
+
+                    if failures >= MAX_INPUT_FAILURES {
+                        let _ = t2.send(AppEvent::InputLost);
+                        break;
+                    }
+
+                    thread::sleep(Duration::from_millis(100 * failures as u64));
+                    AppEvent::InputError(err.to_string())
+                }
+            };
+
+            if t2.send(app_event).is_err() {
+                break;
+            }
+        }
     });
 
+    let order = (0..titles.titles.len()).collect();
+
     let _app_result = App {
         title: 0,
+        order,
+        position: 0,
+        shuffle: false,
         playing: false,
         transfered: false,
         exit: false,
         handles: clients,
+        waiting,
+        max_clients: profile.max_clients,
+        transfer: None,
+        deferred: Vec::new(),
+        deferred_play_state: None,
+        play_after_transfer: false,
         event_channel: rx,
+        event_sender: tx,
         titles,
-        current_grading: Grading {
-            title: None,
-            interpret: None,
-        },
+        current_grading: Grading::default(),
         grading_history: Vec::new(),
+        graded_at: Vec::new(),
+        player_grading_history: Vec::new(),
+        grading_mode: GradingMode::default(),
+        player_gradings: HashMap::new(),
+        player_points: HashMap::new(),
+        status,
+        scoring: Scoring {
+            title_points: profile.title_points.unwrap_or(1),
+            interpret_points: profile.interpret_points.unwrap_or(1),
+        },
+        grade_style: profile.grade_style.unwrap_or_default(),
+        input_warning: None,
+        notice: None,
+        clock: GameClock::default(),
+        timer_follows_playback: profile.timer_follows_playback,
+        max_duration: profile
+            .max_duration
+            .map(|minutes| Duration::from_secs(minutes * 60)),
+        auto_advance: profile.auto_advance.map(Duration::from_secs),
+        idle_timeout: profile.idle_timeout.map(Duration::from_secs),
+        revealed_at: None,
+        game_over: None,
+        confirmed: false,
+        client_filter: String::new(),
+        jump: None,
+        edit: None,
+        titles_path: args.save_edits.then(|| args.titles.clone()),
+        countdown_secs: profile.countdown.unwrap_or(DEFAULT_COUNTDOWN),
+        countdown_generation: 0,
+        countdown: None,
+        max_replays: profile.max_replays,
+        replays_used: 0,
+        filtering: false,
+        show_help: false,
+        history: None,
+        selected_client: None,
+        clues_revealed: 0,
+        no_audio: profile.no_audio,
+        preload: profile.preload,
+        preload_worker: None,
+        preloaded: None,
+        song_hashes: Arc::new(Mutex::new(HashMap::new())),
+        revealed: false,
+        export_m3u: args.export_m3u,
+        audit_log: args.audit_log,
+        results: args.results,
+        quiz_name: results_quiz_name,
+        skipped: Vec::new(),
+        buzzes: Vec::new(),
+        guesses: HashMap::new(),
+        grader: profile.grader.clone(),
+        pending_grades: HashSet::new(),
+        song_generation: 0,
+        music_dir,
+        extensions: profile
+            .extensions
+            .unwrap_or_else(|| DEFAULT_EXTENSIONS.map(String::from).to_vec()),
+        transfer_threads: profile
+            .transfer_threads
+            .unwrap_or(DEFAULT_TRANSFER_THREADS)
+            .max(1) as usize,
     }
     .run(&mut terminal);
 
@@ -494,17 +3729,608 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn stream_file(stream: &mut TcpStream, path: &str) -> Result<(), Box<dyn Error>> {
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn popup_area(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+    let vertical = Layout::vertical([Constraint::Percentage(percent_y)]).flex(Flex::Center);
+    let horizontal = Layout::horizontal([Constraint::Percentage(percent_x)]).flex(Flex::Center);
+    let [area] = vertical.areas(area);
+    let [area] = horizontal.areas(area);
+    area
+}
+
+fn init_terminal() -> Result<DefaultTerminal, ServerError> {
+    if !io::stdout().is_terminal() {
+        let err = io::Error::new(io::ErrorKind::Unsupported, "stdout is not a terminal");
+        return Err(ServerError::Terminal(err));
+    }
+
+    ratatui::try_init().map_err(|err| {
+        ratatui::restore();
+        ServerError::Terminal(err)
+    })
+}
+
+/// Sends the song at `path` to the targets as a preload start followed by chunks, returns
+/// false if it was cancelled before the end. Only each client's write lock is held, and
+/// only for one message, so neither the host nor the other clients wait on a slow one.
+fn preload_file(
+    mut targets: Vec<(TcpStream, Arc<Mutex<()>>)>,
+    path: &Path,
+    cancel: &AtomicBool,
+) -> Result<bool, ServerError> {
     let mut file = File::open(path)?;
     let file_size = file.metadata()?.len();
 
-    let mut bytes: Vec<u8> = vec![0; file_size as usize];
-    file.read_exact(&mut bytes)?;
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    let mut start = string_payload(extension);
+    start.extend_from_slice(&file_size.to_be_bytes());
+    let mut send = |command: &Command, payload: &[u8]| {
+        // A client failing here is reaped like any other, it just misses the preload.
+        targets.retain_mut(|(stream, write_lock)| {
+            let _writing = write_lock.lock().unwrap();
+            !cancel.load(Ordering::Relaxed)
+                && write_framed_message(stream, command, payload).is_ok()
+        });
+        !cancel.load(Ordering::Relaxed)
+    };
+    if !send(&Command::PreloadStart, &start) {
+        return Ok(false);
+    }
+
+    let mut chunk = vec![0_u8; TRANSFER_CHUNK];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        if !send(&Command::PreloadChunk, &chunk[..read]) {
+            return Ok(false);
+        }
+    }
+}
+
+/// Tells a client that reconnected holding the song at `path` to keep it instead of
+/// sending the song again. Only the first transfer after connecting is checked, later
+/// ones may be for something else than what the client held back then. A song not
+/// hashed yet is sent again.
+fn keeps_song(client: &mut Client, path: &Path, hash: Option<u64>) -> bool {
+    let held = client.song_hash.take();
+    if held.is_none() || held != hash {
+        return false;
+    }
 
-    let size_as_bytes = file_size.to_be_bytes();
+    info!("{} holds {} already", client.nickname, path.display());
+    let _writing = client.write_lock.lock().unwrap();
+    write_framed_message(&mut client.stream, &Command::KeepSong, &[]).is_ok()
+}
+
+/// FNV-1a over the song's bytes, the client hashes the same way.
+fn song_hash(mut data: impl Read) -> io::Result<u64> {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut buffer = vec![0_u8; TRANSFER_CHUNK];
+    loop {
+        let read = data.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hash);
+        }
+        for &byte in &buffer[..read] {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Writes the song as a Transfer message, its file extension first so the client can
+/// pick the decoder, calling `progress` with the bytes sent so far after each chunk.
+fn stream_file(
+    stream: &mut TcpStream,
+    path: &Path,
+    mut progress: impl FnMut(u64),
+) -> Result<(), ServerError> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
 
+    let format = string_payload(path.extension().and_then(|ext| ext.to_str()).unwrap_or_default());
+    let size_as_bytes = file_size.to_be_bytes();
+    let length = (format.len() + size_as_bytes.len()) as u64 + file_size;
+    write_frame_header(stream, &Command::Transfer, length)?;
+    stream.write_all(&format)?;
     stream.write_all(&size_as_bytes)?;
-    stream.write_all(&bytes)?;
+
+    let mut chunk = vec![0_u8; TRANSFER_CHUNK];
+    let mut sent = 0;
+    while sent < file_size {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        stream.write_all(&chunk[..read])?;
+        sent += read as u64;
+        progress(sent);
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn titles_error(content: &str) -> String {
+        match parse_titles(content) {
+            Ok(_) => panic!("{} was accepted", content),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn titles_file_is_read_with_defaults() {
+        let content = r#"{"name": "Pop", "titles": [{"title": "Hey", "interpret": "Dom"}]}"#;
+        let titles = parse_titles(content).unwrap();
+        assert_eq!(titles.name.as_deref(), Some("Pop"));
+        assert_eq!(titles.titles.len(), 1);
+        assert_eq!(titles.titles[0].interpret, "Dom");
+        assert!(titles.titles[0].clues.is_empty());
+        assert!(!titles.titles[0].warmup);
+    }
+
+    #[test]
+    fn empty_or_broken_titles_files_are_rejected() {
+        assert!(matches!(parse_titles(""), Err(ServerError::Decode(_))));
+        let cut_off = parse_titles("{\"titles\": [");
+        assert!(matches!(cut_off, Err(ServerError::Decode(_))));
+        assert!(titles_error(r#"{"titles": []}"#).contains("the titles list is empty"));
+    }
+
+    #[test]
+    fn titles_errors_name_the_broken_entry() {
+        let missing = r#"{"titles": [{"title": "A", "interpret": "B"}, {"title": "C"}]}"#;
+        let error = titles_error(missing);
+        assert!(error.starts_with("invalid titles file: title 2: "));
+        assert!(error.contains("interpret"));
+
+        let wrong_type = r#"{"titles": [{"title": "A", "interpret": "B"}, 7]}"#;
+        assert!(titles_error(wrong_type).contains("title 2"));
+
+        let blank = r#"{"titles": [{"title": " ", "interpret": "B"}]}"#;
+        assert!(titles_error(blank).ends_with("title 1: the title is blank"));
+
+        let blank = r#"{"titles": [{"title": "A", "interpret": ""}]}"#;
+        assert!(titles_error(blank).ends_with("title 1: the interpret is blank"));
+    }
+
+    /// A host for the given number of songs with nobody connected.
+    fn test_app(songs: usize) -> App {
+        let (event_sender, event_channel) = mpsc::channel();
+        let title = |number: usize| TitleInfo {
+            title: format!("Title {}", number),
+            interpret: format!("Interpret {}", number),
+            ..TitleInfo::default()
+        };
+        App {
+            title: 0,
+            order: (0..songs).collect(),
+            position: 0,
+            shuffle: false,
+            playing: false,
+            transfered: false,
+            exit: false,
+            handles: Arc::new(Mutex::new(Vec::new())),
+            waiting: Arc::new(Mutex::new(Vec::new())),
+            max_clients: None,
+            transfer: None,
+            deferred: Vec::new(),
+            deferred_play_state: None,
+            play_after_transfer: false,
+            event_channel,
+            event_sender,
+            titles: TitleList {
+                name: None,
+                titles: (1..=songs).map(title).collect(),
+            },
+            current_grading: Grading::default(),
+            grading_history: Vec::new(),
+            graded_at: Vec::new(),
+            player_grading_history: Vec::new(),
+            grading_mode: GradingMode::default(),
+            player_gradings: HashMap::new(),
+            player_points: HashMap::new(),
+            status: None,
+            scoring: Scoring {
+                title_points: 1,
+                interpret_points: 2,
+            },
+            grade_style: GradeStyle::default(),
+            input_warning: None,
+            notice: None,
+            clock: GameClock::default(),
+            timer_follows_playback: false,
+            max_duration: None,
+            auto_advance: None,
+            idle_timeout: None,
+            revealed_at: None,
+            game_over: None,
+            confirmed: true,
+            client_filter: String::new(),
+            jump: None,
+            edit: None,
+            titles_path: None,
+            countdown_secs: DEFAULT_COUNTDOWN,
+            countdown_generation: 0,
+            countdown: None,
+            max_replays: None,
+            replays_used: 0,
+            filtering: false,
+            show_help: false,
+            history: None,
+            selected_client: None,
+            clues_revealed: 0,
+            no_audio: true,
+            preload: false,
+            preload_worker: None,
+            preloaded: None,
+            song_hashes: Arc::new(Mutex::new(HashMap::new())),
+            revealed: false,
+            export_m3u: None,
+            audit_log: None,
+            results: None,
+            quiz_name: None,
+            skipped: Vec::new(),
+            buzzes: Vec::new(),
+            guesses: HashMap::new(),
+            grader: None,
+            pending_grades: HashSet::new(),
+            song_generation: 0,
+            music_dir: PathBuf::from(DEFAULT_MUSIC_DIR),
+            extensions: DEFAULT_EXTENSIONS.map(String::from).to_vec(),
+            transfer_threads: 1,
+        }
+    }
+
+    fn grading(title: Option<bool>, interpret: Option<bool>) -> Grading {
+        Grading {
+            title,
+            interpret,
+            disputed: false,
+        }
+    }
+
+    #[test]
+    fn undo_takes_back_the_last_round_everywhere() {
+        let mut app = test_app(3);
+        app.grading_mode = GradingMode::Player;
+
+        app.player_gradings
+            .insert("Dom".to_string(), grading(Some(true), Some(true)));
+        app.next();
+        let second_round = HashMap::from([
+            ("Dom".to_string(), grading(Some(true), Some(false))),
+            ("Ann".to_string(), grading(None, Some(true))),
+        ]);
+        app.player_gradings = second_round.clone();
+        app.current_grading = grading(Some(false), Some(true));
+        app.next();
+        assert_eq!(app.position, 2);
+        assert_eq!(app.player_points["Dom"], 4);
+        assert_eq!(app.player_points["Ann"], 2);
+
+        app.undo_grading();
+        assert_eq!(app.position, 1);
+        assert_eq!(app.title, 1);
+        assert_eq!(app.grading_history.len(), 1);
+        assert_eq!(app.graded_at.len(), 1);
+        assert_eq!(app.player_grading_history.len(), 1);
+        assert_eq!(app.player_grading_history[0]["Dom"].interpret, Some(true));
+        assert_eq!(app.player_points["Dom"], 3);
+        assert_eq!(app.player_points["Ann"], 0);
+        assert_eq!(app.current_grading.interpret, Some(true));
+        assert_eq!(app.player_gradings["Ann"].interpret, Some(true));
+        assert_eq!(app.player_gradings.len(), second_round.len());
+
+        // Grading it again lands in the same place as before.
+        app.next();
+        assert_eq!(app.position, 2);
+        assert_eq!(app.grading_history.len(), app.player_grading_history.len());
+        assert_eq!(app.graded_at.len(), 2);
+        assert_eq!(app.player_points["Dom"], 4);
+        assert_eq!(app.player_points["Ann"], 2);
+    }
+
+    fn verdict(generation: u64) -> AppEvent {
+        AppEvent::AutoGraded {
+            nickname: "Dom".to_string(),
+            generation,
+            grading: Some(grading(Some(true), None)),
+        }
+    }
+
+    #[test]
+    fn late_verdict_is_not_applied_to_a_song_jumped_to() {
+        let mut app = test_app(3);
+        app.pending_grades.insert("Dom".to_string());
+        let before_jump = app.song_generation;
+
+        app.jump_to(3);
+        app.pending_grades.insert("Dom".to_string());
+        app.event_sender.send(verdict(before_jump)).unwrap();
+        app.handle_events().unwrap();
+        assert!(app.player_gradings.is_empty());
+        assert!(app.pending_grades.contains("Dom"));
+
+        app.event_sender.send(verdict(app.song_generation)).unwrap();
+        app.handle_events().unwrap();
+        assert_eq!(app.player_gradings["Dom"].title, Some(true));
+        assert!(app.pending_grades.is_empty());
+    }
+
+    #[test]
+    fn undo_without_history_changes_nothing() {
+        let mut app = test_app(2);
+        app.undo_grading();
+        assert_eq!(app.position, 0);
+        assert!(app.grading_history.is_empty() && app.graded_at.is_empty());
+    }
+
+    #[test]
+    fn grader_output_grades_title_and_interpret() {
+        let grading = grader_verdict(true, b"correct incorrect\n");
+        assert_eq!(grading.title, Some(true));
+        assert_eq!(grading.interpret, Some(false));
+
+        let grading = grader_verdict(false, b"0 1");
+        assert_eq!(grading.title, Some(false));
+        assert_eq!(grading.interpret, Some(true));
+    }
+
+    #[test]
+    fn silent_grader_grades_by_exit_status() {
+        let passed = grader_verdict(true, b"");
+        assert_eq!((passed.title, passed.interpret), (Some(true), Some(true)));
+
+        let failed = grader_verdict(false, b"  \n");
+        assert_eq!((failed.title, failed.interpret), (Some(false), Some(false)));
+    }
+
+    #[test]
+    fn malformed_grader_output_leaves_the_grading_open() {
+        let grading = grader_verdict(true, b"maybe correct");
+        assert_eq!((grading.title, grading.interpret), (None, Some(true)));
+
+        let grading = grader_verdict(true, b"correct");
+        assert_eq!((grading.title, grading.interpret), (Some(true), None));
+
+        let grading = grader_verdict(true, &[0xff, 0xfe]);
+        assert!(!grading.disputed);
+        assert_eq!((grading.title, grading.interpret), (None, None));
+    }
+
+    /// A connected pair, the server side reading with the given timeout.
+    fn socket_pair(timeout: Duration) -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_read_timeout(Some(timeout)).unwrap();
+        (server, client)
+    }
+
+    fn reader_events(server: TcpStream) -> mpsc::Receiver<AppEvent> {
+        let (sender, events) = mpsc::channel();
+        thread::spawn(move || client_reader(1, "Dom".to_string(), server, sender));
+        events
+    }
+
+    /// A connected client joined long enough ago to count as idle, with its far end.
+    fn idle_client(id: usize, nickname: &str) -> (Client, TcpStream) {
+        let (stream, peer) = socket_pair(Duration::from_secs(1));
+        let client = Client {
+            id,
+            stream,
+            address: None,
+            song_hash: None,
+            nickname: nickname.to_string(),
+            color: None,
+            mirror: false,
+            last_activity: Instant::now() - Duration::from_secs(60),
+            write_lock: Arc::new(Mutex::new(())),
+            receiving: false,
+        };
+        (client, peer)
+    }
+
+    #[test]
+    fn client_sending_only_heartbeats_is_reaped() {
+        let mut app = test_app(1);
+        app.idle_timeout = Some(Duration::from_secs(30));
+        let (client, _peer) = idle_client(1, "Dom");
+        app.handles.lock().unwrap().push(client);
+
+        app.event_sender
+            .send(AppEvent::ClientRequest(1, Request::Heartbeat))
+            .unwrap();
+        app.handle_events().unwrap();
+        app.reap_idle_clients();
+        assert!(app.handles.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn buzzing_keeps_a_client_from_idling() {
+        let mut app = test_app(1);
+        app.idle_timeout = Some(Duration::from_secs(30));
+        let (client, _peer) = idle_client(1, "Dom");
+        app.handles.lock().unwrap().push(client);
+
+        let buzz = AppEvent::Buzz {
+            id: 1,
+            nickname: "Dom".to_string(),
+            at: Instant::now(),
+        };
+        app.event_sender.send(buzz).unwrap();
+        app.handle_events().unwrap();
+        app.reap_idle_clients();
+        assert_eq!(app.handles.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn summary_of_a_game_ending_during_a_transfer_follows_it() {
+        let mut app = test_app(1);
+        let (client, mut peer) = idle_client(1, "Dom");
+        app.handles.lock().unwrap().push(client);
+        app.transfer = Some((0, 1));
+
+        app.send_summary();
+        assert_eq!(app.deferred.len(), 1);
+
+        app.finish_transfer(Vec::new());
+        let mut header = [0_u8; 13];
+        peer.read_exact(&mut header).unwrap();
+        assert_eq!(&header[..4], b"MUSQ");
+        assert_eq!(header[12], 18);
+    }
+
+    #[test]
+    fn client_getting_a_resend_is_resynced_instead_of_commanded() {
+        let mut app = test_app(1);
+        let (mut receiving, mut receiving_peer) = idle_client(1, "Dom");
+        receiving.receiving = true;
+        let (other, mut other_peer) = idle_client(2, "Ann");
+        app.handles.lock().unwrap().extend([receiving, other]);
+
+        app.deliver(Deferred {
+            command: Command::Pause,
+            payload: Some(Vec::new()),
+            player_payloads: HashMap::new(),
+        });
+        let mut header = [0_u8; 13];
+        other_peer.read_exact(&mut header).unwrap();
+        assert_eq!(header[12], 3);
+
+        // The pause passed the resent client by, the resync is the first it hears of it.
+        app.finish_resend(1, true);
+        receiving_peer.read_exact(&mut header).unwrap();
+        assert_eq!(header[12], 12);
+        assert!(app.handles.lock().unwrap().iter().all(|c| !c.receiving));
+    }
+
+    #[test]
+    fn held_song_is_kept_by_its_cached_hash() {
+        // The file does not exist, only the cached hash is compared.
+        let path = Path::new("missing.mp3");
+        let (mut client, mut peer) = idle_client(1, "Dom");
+        client.song_hash = Some(7);
+        assert!(!keeps_song(&mut client, path, None));
+
+        client.song_hash = Some(7);
+        assert!(!keeps_song(&mut client, path, Some(8)));
+        assert!(client.song_hash.is_none());
+
+        client.song_hash = Some(7);
+        assert!(keeps_song(&mut client, path, Some(7)));
+        let mut header = [0_u8; 13];
+        peer.read_exact(&mut header).unwrap();
+        assert_eq!(header[12], 24);
+    }
+
+    #[test]
+    fn preload_is_sent_without_the_client_list() {
+        let name = format!("musicquiz-preload-{}.mp3", process::id());
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, b"song").unwrap();
+        let (client, mut peer) = idle_client(1, "Dom");
+        let handles = Mutex::new(vec![client]);
+
+        // The list stays locked throughout, the preload must not need it.
+        let clients = handles.lock().unwrap();
+        let stream = clients[0].stream.try_clone().unwrap();
+        let target = (stream, clients[0].write_lock.clone());
+        let sent = preload_file(vec![target], &path, &AtomicBool::new(false));
+        fs::remove_file(&path).unwrap();
+        assert!(sent.unwrap());
+        drop(clients);
+
+        let mut received = Vec::new();
+        let timeout = Duration::from_millis(200);
+        peer.set_read_timeout(Some(timeout)).unwrap();
+        let _ = peer.read_to_end(&mut received);
+        assert_eq!(received[12], 21);
+        assert!(received.ends_with(b"song"));
+        assert_eq!(received[received.len() - 5], 22);
+    }
+
+    #[test]
+    fn play_state_is_held_back_only_once_during_a_transfer() {
+        let mut app = test_app(1);
+        app.no_audio = false;
+        app.transfer = Some((0, 1));
+        for _ in 0..5 {
+            app.sync_play_state();
+        }
+        assert!(app.deferred.is_empty());
+        assert_eq!(app.deferred_play_state, Some(false));
+
+        app.finish_transfer(Vec::new());
+        assert!(app.deferred_play_state.is_none());
+    }
+
+    #[test]
+    fn stalled_client_is_dropped_after_repeated_timeouts() {
+        let (server, _client) = socket_pair(Duration::from_millis(50));
+        let events = reader_events(server);
+        let event = events.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(matches!(event, AppEvent::ClientLeft(1)));
+    }
+
+    #[test]
+    fn heartbeats_keep_a_client_from_stalling() {
+        let (server, mut client) = socket_pair(Duration::from_millis(100));
+        let events = reader_events(server);
+        for _ in 0..4 {
+            client.write_all(&[7]).unwrap();
+            let event = events.recv_timeout(Duration::from_secs(2)).unwrap();
+            assert!(matches!(
+                event,
+                AppEvent::ClientRequest(1, Request::Heartbeat)
+            ));
+            thread::sleep(Duration::from_millis(150));
+        }
+    }
+
+    #[test]
+    fn client_stalling_inside_a_guess_is_dropped() {
+        let (server, mut client) = socket_pair(Duration::from_millis(50));
+        let events = reader_events(server);
+        // A guess code and the start of its length, then nothing.
+        client.write_all(&[9, 0, 0]).unwrap();
+        let event = events.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(matches!(event, AppEvent::ClientLeft(1)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hanging_grader_is_killed() {
+        let mut command = process::Command::new("sleep");
+        command.arg("10");
+        let started = Instant::now();
+        let result = run_with_timeout(command, Duration::from_millis(100));
+        assert!(result.is_err_and(|err| err.kind() == io::ErrorKind::TimedOut));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}